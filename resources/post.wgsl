@@ -4,6 +4,7 @@ struct PostUniforms {
     bloom_offset: vec2<f32>,
     bloom_sample_bias: f32,
     bloom_multiplier: f32,
+    flash: f32,
 };
 @group(0) @binding(0)
 var<uniform> uniforms: PostUniforms;
@@ -84,5 +85,6 @@ fn fs_main(in: VertOutput) -> @location(0) vec4<f32> {
     let noise = textureSample(t_noise, s, in.v_uv * noise_scale).rgb - 0.5;
     let bloom = textureSample(t_bloom, s, in.v_uv);
     let color = textureSample(t_lit, s, in.v_uv).rgb + bloom.rgb * uniforms.bloom_multiplier;
-    return vec4<f32>(aces_fitted(color) + noise * 0.01, 1.);
+    let color = mix(aces_fitted(color), vec3<f32>(1.), uniforms.flash);
+    return vec4<f32>(color + noise * 0.01, 1.);
 }
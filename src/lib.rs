@@ -6,19 +6,90 @@ mod sync;
 use color_space::Hsv;
 use glam::*;
 use include_dir::{include_dir, Dir};
-pub use player::Player;
+pub use player::{
+    AnalysisFrame, AnalysisTable, AudioBackend, AudioStats, Band, BeatInfo, ChannelMode, Loudness,
+    Player, StereoLevels, TrackerPosition, WindowFunction,
+};
 use rand::prelude::*;
 pub use renderer::Renderer;
 use scene::{Camera, CameraView, Instance, Light, Model, Scene, VertexData};
 use simdnoise::*;
 use std::time::Instant;
-pub use sync::DemoSync;
+#[cfg(debug_assertions)]
+pub use sync::MockEditor;
+pub use sync::{check_tracks, convert_tracks, DemoSync, TrackReport};
+use sync::{ParamTracks, ResolveTrack};
 
 pub static RESOURCES_PATH: &str = "resources";
 pub static RESOURCES_DIR: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/resources");
 
 const PARTICLES_COUNT: usize = 2048;
 const HEIGHTMAP_SIZE: usize = 1000;
+const CAMERA_COUNT: usize = 2;
+const LIGHT_COUNT: usize = 2;
+
+sync::sync_params! {
+    /// Scene-wide sync values
+    struct SceneParams {
+        camera: f32 = "camera",
+        ambient: f32 = "ambient",
+        bloom_floor: f32 = "bloom_floor",
+        bloom_amount: f32 = "bloom_amount",
+        march_multiplier: f32 = "march_multiplier",
+        triangles: f32 = "triangles",
+        beat_multiplier: f32 = "beat_multiplier",
+    }
+
+    struct GreetParams {
+        index: f32 = "index",
+        scale: Vec3 = "scale",
+        rotation: Vec3 = "rotation",
+        translation: Vec3 = "translation",
+    }
+
+    struct CameraParams {
+        fov: f32 = "fov",
+        pos: Vec3 = "pos",
+        view: f32 = "view",
+        angles: Vec3 = ["pitch", "yaw", "roll"],
+        target: Vec3 = "target",
+    }
+
+    struct LightParams {
+        coord: Vec4 = "coord",
+        color: Hsv = ["hue", "saturation", "value"],
+    }
+}
+
+/// Tracks of all sync values which [`State`] reads
+struct SyncTracks {
+    scene: ParamTracks<SceneParams>,
+    greet: ParamTracks<GreetParams>,
+    cameras: Vec<ParamTracks<CameraParams>>,
+    lights: Vec<ParamTracks<LightParams>>,
+}
+
+impl SyncTracks {
+    fn new(resolve: &mut impl ResolveTrack) -> Self {
+        Self {
+            scene: ParamTracks::new("", resolve),
+            greet: ParamTracks::new("greet", resolve),
+            cameras: (0..CAMERA_COUNT)
+                .map(|i| ParamTracks::new(&format!("camera{i}"), resolve))
+                .collect(),
+            lights: (0..LIGHT_COUNT)
+                .map(|i| ParamTracks::new(&format!("light{i}"), resolve))
+                .collect(),
+        }
+    }
+}
+
+/// Names of all sync tracks which the demo reads
+pub fn sync_tracks() -> Vec<String> {
+    let mut names = Vec::new();
+    SyncTracks::new(&mut names);
+    names
+}
 
 fn cylinder_position(r: f32, u: f32, v: f32) -> Vec3 {
     let u = u * std::f32::consts::TAU;
@@ -312,10 +383,11 @@ pub struct State {
     particles: ParticleSystem,
     scene: Scene,
     greet_models_start: usize,
+    tracks: SyncTracks,
 }
 
 impl State {
-    pub fn new(rng: &mut impl Rng) -> (State, Vec<Model>) {
+    pub fn new(rng: &mut impl Rng, sync: &mut DemoSync) -> (State, Vec<Model>) {
         // Add leaf model for particle system
         let mut models = vec![Model {
             vertices: generate_leaf(Hsv::new(100., 0.5, 0.5), 0.3),
@@ -422,7 +494,11 @@ impl State {
             bloom_amount: 1.,
             march_multiplier: 1.,
             world_triangles: 1.,
-            lights: std::iter::repeat(Light::default()).take(2).collect(),
+            flash: 0.,
+            beat: 0.,
+            lights: std::iter::repeat(Light::default())
+                .take(LIGHT_COUNT)
+                .collect(),
             camera: Camera::default(),
         };
         (
@@ -432,6 +508,7 @@ impl State {
                 particles,
                 scene,
                 greet_models_start,
+                tracks: SyncTracks::new(sync),
             },
             models,
         )
@@ -456,46 +533,50 @@ impl State {
             instances.clear();
         }
         // Add a single greet text for the configured index
+        let greet = self.tracks.greet.read(sync);
         let max_i = self.scene.instances_by_model.len() - 1;
-        self.scene.instances_by_model
-            [(self.greet_models_start + sync.get("greet:index") as usize).min(max_i)]
-        .push(Instance {
-            scale: sync.get_vec3("greet:scale", None),
-            rotation: Quat::from_euler(
-                EulerRot::XYZ,
-                sync.get("greet:rotation.x"),
-                sync.get("greet:rotation.y"),
-                sync.get("greet:rotation.z"),
-            ),
-            translation: sync.get_vec3("greet:translation", None),
-        });
+        self.scene.instances_by_model[(self.greet_models_start + greet.index as usize).min(max_i)]
+            .push(Instance {
+                scale: greet.scale,
+                rotation: Quat::from_euler(
+                    EulerRot::XYZ,
+                    greet.rotation.x,
+                    greet.rotation.y,
+                    greet.rotation.z,
+                ),
+                translation: greet.translation,
+            });
 
         // Update camera
-        let camera = sync.get("camera") as usize;
-        let camstr = format!("camera{camera}");
+        let params = self.tracks.scene.read(sync);
+        // The camera track is interpolated like any other, so while easing between cameras
+        // or after a typo in the editor it can go past the last one, which keeps it in view
+        let camera = self.tracks.cameras[(params.camera as usize).min(CAMERA_COUNT - 1)].read(sync);
         self.scene.camera = Camera {
-            fov: sync.get(&[&camstr, "fov"].join(":")),
-            position: sync.get_vec3(&[&camstr, "pos"].join(":"), None),
-            view: if sync.get(&[&camstr, "view"].join(":")) < 1. {
-                CameraView::PitchYawRoll(sync.get_vec3(&camstr, Some([":pitch", ":yaw", ":roll"])))
+            fov: camera.fov,
+            position: camera.pos,
+            view: if camera.view < 1. {
+                CameraView::PitchYawRoll(camera.angles)
             } else {
-                CameraView::Target(sync.get_vec3(&[&camstr, "target"].join(":"), None))
+                CameraView::Target(camera.target)
             },
         };
 
         // Update lights
-        for (i, light) in self.scene.lights.iter_mut().enumerate() {
-            let lightstr = format!("light{i}");
+        for (light, tracks) in self.scene.lights.iter_mut().zip(&self.tracks.lights) {
+            let params = tracks.read(sync);
             *light = Light {
-                coordinates: sync.get_vec4(&[&lightstr, "coord"].join(":"), None),
-                color: sync.get_hsv(&lightstr, None),
+                coordinates: params.coord,
+                color: params.color,
             };
         }
-        self.scene.ambient = sync.get("ambient");
-        self.scene.bloom_floor = sync.get("bloom_floor");
-        self.scene.bloom_amount = sync.get("bloom_amount");
-        self.scene.march_multiplier = sync.get("march_multiplier");
-        self.scene.world_triangles = sync.get("triangles");
+        self.scene.ambient = params.ambient;
+        self.scene.bloom_floor = params.bloom_floor;
+        self.scene.bloom_amount = params.bloom_amount;
+        self.scene.march_multiplier = params.march_multiplier;
+        self.scene.world_triangles = params.triangles;
+        self.scene.flash = sync.get_flash();
+        self.scene.beat = sync.get_beat() * params.beat_multiplier;
 
         &self.scene
     }
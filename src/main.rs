@@ -1,7 +1,7 @@
 mod logger;
 
 use anyhow::{anyhow, Context, Result};
-use demo::{DemoSync, Player, Renderer};
+use demo::{AudioBackend, DemoSync, Player, Renderer};
 use pico_args::Arguments;
 use rand::prelude::*;
 use rand_xoshiro::Xoshiro128Plus;
@@ -9,11 +9,16 @@ use rand_xoshiro::Xoshiro128Plus;
 use winit::platform::unix::WindowBuilderExtUnix;
 use winit::{
     dpi::PhysicalSize,
-    event::{Event, KeyboardInput, VirtualKeyCode, WindowEvent},
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     window::{Fullscreen, WindowBuilder},
 };
 
+// Audio latency adjustment per key press in seconds
+const LATENCY_STEP: f32 = 0.005;
+// Rows which sync loops over with live input, unless chosen with --loop-rows
+const LIVE_LOOP_ROWS: (f32, f32) = (0., 512.);
+
 fn print_help() {
     print!(
         r#"List of available options:
@@ -21,9 +26,53 @@ fn print_help() {
     --benchmark         Log frametimes
     -s, --scale         Set the rendering scale (default 1.0)
     --list-monitors     List available monitors and video modes
+    --convert-tracks input output
+                        Convert sync tracks between tracks.bin, Rocket XML
+                        (.rocket or .xml) and CSV, chosen by file extension
+    --check-sync        List missing and unused sync tracks and exit. Release
+                        builds also check this at startup.
+    --list-audio-devices
+                        List available audio hosts, output and input devices
+    --audio-host name   Specify an audio host to use
+    --audio-device dev  Specify an audio output device by index or name
+    --audio-latency-ms ms
+                        Compensate for audio output latency instead of using
+                        the latency reported by the audio device
+    --live-input        Analyze audio from an input device instead of playing
+                        music, looping sync over --loop-rows (default 0:512)
+    --input-device dev  Specify an audio input device by index or name
+    --music file        Play a file from resources instead of music.ogg. Sync rows
+                        follow pattern rows of FastTracker 2 XM modules, and
+                        .song files are synthesized when loading.
+    --calibrate-latency Play a click track and flash the screen on each click,
+                        to find the right --audio-latency-ms
     --monitor id        Specify a monitor to use in fullscreen
     --exclusive mode    Exclusive fullscreen (see --list-monitors for modes)
     --windowed          Don't go fullscreen
+    --null-audio        Don't output audio, advance time in real time
+    --fixed-fps fps     Don't output audio, advance time by 1/fps every frame
+    --volume v          Set the music volume (default 1.0)
+    --mute              Start with music muted
+    --fade-in secs      Fade the music in over secs seconds from the start
+    --fade-out secs     Fade the music out over secs seconds before the end
+    --gain-track track  Drive the music volume from a sync track, eg. audio:gain
+    --loudness-attack-ms ms
+                        Rise time of the smoothed loudness envelope (default 10)
+    --loudness-release-ms ms
+                        Fall time of the smoothed loudness envelope (default 300)
+    --loop start:end    Repeat playback between start and end seconds
+    --loop-rows start:end
+                        Repeat playback between start and end rows
+    --export-analysis file
+                        Write the precomputed audio analysis as CSV and exit
+    --save-analysis     Save the audio analysis to resources/analysis.bin for
+                        faster starts and release builds, and exit. Debug
+                        builds only.
+
+Press M to mute or unmute the music. Up and down arrows adjust audio latency.
+In debug builds, keys 1-4 set the playback speed to 0.25x, 0.5x, 1x and 2x,
+and P toggles keeping the original pitch at other speeds.
+A and B set the loop start and end to the current row, C clears the loop.
 
 To force X11 or Wayland, set the environment variable
 WINIT_UNIX_BACKEND to x11 or wayland.
@@ -31,6 +80,13 @@ WINIT_UNIX_BACKEND to x11 or wayland.
     );
 }
 
+fn parse_range(s: &str) -> Result<(f32, f32)> {
+    let (start, end) = s
+        .split_once(':')
+        .context("Range must be in the form start:end")?;
+    Ok((start.trim().parse()?, end.trim().parse()?))
+}
+
 struct DisplayConfiguration {
     title: &'static str,
     monitor: Option<usize>,
@@ -55,6 +111,68 @@ fn list_monitors() {
     }
 }
 
+fn list_audio_devices() {
+    use cpal::traits::{DeviceTrait, HostTrait};
+
+    let default_host = cpal::default_host().id();
+    for id in cpal::available_hosts() {
+        let default = if id == default_host { " (default)" } else { "" };
+        println!("Audio host {}{}", id.name(), default);
+
+        let host = match cpal::host_from_id(id) {
+            Ok(host) => host,
+            Err(e) => {
+                println!("   Unavailable: {}", e);
+                continue;
+            }
+        };
+        let default_device = host.default_output_device().and_then(|d| d.name().ok());
+        let devices = match host.output_devices() {
+            Ok(devices) => devices,
+            Err(e) => {
+                println!("   Unavailable: {}", e);
+                continue;
+            }
+        };
+        for (i, device) in devices.enumerate() {
+            let name = device.name().unwrap_or_else(|_| "(unknown)".into());
+            let default = if Some(&name) == default_device.as_ref() {
+                " (default)"
+            } else {
+                ""
+            };
+            println!("   Device {}: {}{}", i, name, default);
+            for conf in device.supported_output_configs().into_iter().flatten() {
+                println!(
+                    "      {} channels {}-{}Hz {:?}",
+                    conf.channels(),
+                    conf.min_sample_rate().0,
+                    conf.max_sample_rate().0,
+                    conf.sample_format()
+                );
+            }
+        }
+
+        let default_device = host.default_input_device().and_then(|d| d.name().ok());
+        let devices = match host.input_devices() {
+            Ok(devices) => devices,
+            Err(e) => {
+                println!("   Input unavailable: {}", e);
+                continue;
+            }
+        };
+        for (i, device) in devices.enumerate() {
+            let name = device.name().unwrap_or_else(|_| "(unknown)".into());
+            let default = if Some(&name) == default_device.as_ref() {
+                " (default)"
+            } else {
+                ""
+            };
+            println!("   Input device {}: {}{}", i, name, default);
+        }
+    }
+}
+
 fn frame(
     rng: &mut Xoshiro128Plus,
     sync: &mut DemoSync,
@@ -71,12 +189,7 @@ fn frame(
     let scene = state.update(rng, sync);
 
     // Render the scene
-    match renderer.render(
-        rng,
-        scene,
-        player.time_secs(),
-        sync.get_beat() * sync.get("beat_multiplier"),
-    ) {
+    match renderer.render(rng, scene, player.time_secs(), scene.beat) {
         Ok(_) => {}
         Err(wgpu::SurfaceError::Lost) => renderer.configure_surface(),
         Err(wgpu::SurfaceError::OutOfMemory) => return ControlFlow::Exit,
@@ -144,7 +257,7 @@ fn run(
 
     // Initialize demo render data
     let mut rng = Xoshiro128Plus::seed_from_u64(0);
-    let (mut state, models) = demo::State::new(&mut rng);
+    let (mut state, models) = demo::State::new(&mut rng, &mut sync);
 
     // Initialize Renderer for window
     let internal_size = PhysicalSize::new(
@@ -166,20 +279,61 @@ fn run(
         window.set_cursor_visible(false);
     }
 
+    // In debug builds, play until a Rocket editor connects and takes over
+    #[cfg(debug_assertions)]
+    if !sync.is_connected() {
+        player.play();
+    }
+
+    // Loop start row marked from keyboard
+    #[cfg(debug_assertions)]
+    let mut loop_start = None;
+
     event_loop.run(move |event, _, control_flow| match event {
         Event::WindowEvent { event, window_id } if window_id == window.id() => match event {
             WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
             WindowEvent::KeyboardInput {
-                input: KeyboardInput {
-                    virtual_keycode, ..
-                },
+                input:
+                    KeyboardInput {
+                        virtual_keycode,
+                        state: key_state,
+                        ..
+                    },
                 ..
-            } => match virtual_keycode {
-                Some(VirtualKeyCode::Q | VirtualKeyCode::Escape) => {
+            } => match (virtual_keycode, key_state) {
+                (Some(VirtualKeyCode::Q | VirtualKeyCode::Escape), _) => {
                     *control_flow = ControlFlow::Exit;
                     #[cfg(not(debug_assertions))]
                     panic!("Thank you for playing Wing Commander!");
                 }
+                (Some(VirtualKeyCode::M), ElementState::Pressed) => {
+                    player.set_muted(!player.is_muted());
+                }
+                (Some(VirtualKeyCode::Up), ElementState::Pressed) => {
+                    player.set_latency(Some(player.latency_secs() + LATENCY_STEP));
+                }
+                (Some(VirtualKeyCode::Down), ElementState::Pressed) => {
+                    player.set_latency(Some(player.latency_secs() - LATENCY_STEP));
+                }
+                // Playback speed and loop for sync authoring
+                #[cfg(debug_assertions)]
+                (Some(key), ElementState::Pressed) => match key {
+                    VirtualKeyCode::Key1 => player.set_playback_rate(0.25),
+                    VirtualKeyCode::Key2 => player.set_playback_rate(0.5),
+                    VirtualKeyCode::Key3 => player.set_playback_rate(1.),
+                    VirtualKeyCode::Key4 => player.set_playback_rate(2.),
+                    VirtualKeyCode::P => player.set_preserve_pitch(!player.preserves_pitch()),
+                    VirtualKeyCode::A => loop_start = Some(sync.get_row().floor()),
+                    VirtualKeyCode::B => {
+                        let start = loop_start.unwrap_or(0.);
+                        sync.set_loop_rows(&mut player, Some((start, sync.get_row().ceil())));
+                    }
+                    VirtualKeyCode::C => {
+                        loop_start = None;
+                        sync.set_loop_rows(&mut player, None);
+                    }
+                    _ => {}
+                },
                 _ => {}
             },
             WindowEvent::Resized(physical_size) => {
@@ -208,6 +362,16 @@ fn main() -> Result<()> {
         list_monitors();
         return Ok(());
     }
+    if pargs.contains("--list-audio-devices") {
+        list_audio_devices();
+        return Ok(());
+    }
+    if let Some(input) = pargs.opt_value_from_str::<_, std::path::PathBuf>("--convert-tracks")? {
+        let output: std::path::PathBuf = pargs.free_from_str()?;
+        demo::convert_tracks(&input, &output)?;
+        println!("Converted {} to {}", input.display(), output.display());
+        return Ok(());
+    }
     let benchmark = pargs.contains("--benchmark");
     let scale = pargs.opt_value_from_str(["-s", "--scale"])?.unwrap_or(1.);
     if !(0.1..=2.).contains(&scale) {
@@ -215,6 +379,59 @@ fn main() -> Result<()> {
     }
     eprintln!("See --help if the default options don't work for you");
 
+    let null_audio = pargs.contains("--null-audio");
+    let audio_host: Option<String> = pargs.opt_value_from_str("--audio-host")?;
+    let audio_device = pargs.opt_value_from_str("--audio-device")?;
+    let backend = match pargs.opt_value_from_str::<_, f32>("--fixed-fps")? {
+        Some(fps) if fps > 0. => {
+            AudioBackend::FixedStep(std::time::Duration::from_secs_f32(1. / fps))
+        }
+        Some(_) => return Err(anyhow!("FPS must be positive")),
+        None if null_audio => AudioBackend::Null,
+        None => AudioBackend::Device {
+            host: audio_host.clone(),
+            device: audio_device,
+        },
+    };
+
+    let music: String = pargs
+        .opt_value_from_str("--music")?
+        .unwrap_or_else(|| "music.ogg".into());
+    let live_input = pargs.contains("--live-input");
+    let input_device: Option<String> = pargs.opt_value_from_str("--input-device")?;
+
+    let loop_secs = pargs.opt_value_from_fn("--loop", parse_range)?;
+    let mut loop_rows = pargs.opt_value_from_fn("--loop-rows", parse_range)?;
+    if loop_secs.is_some() && loop_rows.is_some() {
+        return Err(anyhow!("--loop and --loop-rows can't be used together"));
+    }
+    if live_input && loop_secs.is_none() && loop_rows.is_none() {
+        loop_rows = Some(LIVE_LOOP_ROWS);
+    }
+
+    let volume = pargs.opt_value_from_str("--volume")?.unwrap_or(1.);
+    let mute = pargs.contains("--mute");
+    let fade_in: Option<f32> = pargs.opt_value_from_str("--fade-in")?;
+    let fade_out: Option<f32> = pargs.opt_value_from_str("--fade-out")?;
+    let gain_track: Option<String> = pargs.opt_value_from_str("--gain-track")?;
+    let latency_ms: Option<f32> = pargs.opt_value_from_str("--audio-latency-ms")?;
+    let loudness_attack_ms = pargs
+        .opt_value_from_str("--loudness-attack-ms")?
+        .unwrap_or(10.);
+    let loudness_release_ms = pargs
+        .opt_value_from_str("--loudness-release-ms")?
+        .unwrap_or(300.);
+    let calibrate = pargs.contains("--calibrate-latency");
+    if calibrate && live_input {
+        return Err(anyhow!(
+            "--calibrate-latency and --live-input can't be used together"
+        ));
+    }
+    let export_analysis: Option<String> = pargs.opt_value_from_str("--export-analysis")?;
+    #[cfg(debug_assertions)]
+    let save_analysis = pargs.contains("--save-analysis");
+    let check_sync = pargs.contains("--check-sync");
+
     let size = PhysicalSize::new(3840, 768);
     let disp = DisplayConfiguration {
         title: "Demo",
@@ -235,11 +452,76 @@ fn main() -> Result<()> {
         .ok();
     log::set_max_level(log::LevelFilter::max());
 
+    // Fail before the show starts if sync tracks are missing
+    if check_sync || cfg!(not(debug_assertions)) {
+        let mut required = demo::sync_tracks();
+        if let Some(track) = &gain_track {
+            if !required.contains(track) {
+                required.push(track.clone());
+            }
+        }
+        let report = demo::check_tracks(&required)?;
+        if check_sync {
+            print!("{}", report);
+        } else if !report.unused.is_empty() {
+            log::warn!("Unused sync tracks: {}", report.unused.join(", "));
+        }
+        if !report.is_ok() {
+            return Err(anyhow!("{}", report));
+        }
+        if check_sync {
+            println!("All {} sync tracks are present", required.len());
+            return Ok(());
+        }
+    }
+
     // Load music
-    let player = Player::new("music.ogg")?;
+    let mut player = if calibrate {
+        Player::click_track(backend)?
+    } else if live_input {
+        Player::live_input(audio_host.as_deref(), input_device.as_deref())?
+    } else {
+        Player::new(&music, backend)?
+    };
+    if let Some(path) = export_analysis {
+        let table = player
+            .analysis_table()
+            .context("No audio analysis available")?;
+        let file =
+            std::fs::File::create(&path).with_context(|| format!("Failed to create {}", path))?;
+        table.write_csv(std::io::BufWriter::new(file))?;
+        log::info!("Wrote {}", path);
+        return Ok(());
+    }
+    #[cfg(debug_assertions)]
+    if save_analysis {
+        return player.save_analysis();
+    }
+    if latency_ms.is_some() {
+        player.set_latency(latency_ms.map(|ms| ms / 1000.));
+    }
+    player.set_volume(volume);
+    player.set_loudness_envelope(loudness_attack_ms / 1000., loudness_release_ms / 1000.);
+    player.set_muted(mute);
+    player.set_fades(
+        fade_in.map(|secs| (0., secs)),
+        fade_out.map(|secs| (player.len_secs() - secs, secs)),
+    );
 
     // Initialize rocket
-    let sync = DemoSync::new(120., 8., benchmark || cfg!(debug_assertions));
+    let mut sync = DemoSync::new(120., 8., benchmark || cfg!(debug_assertions));
+    sync.set_gain_track(gain_track.as_deref());
+    sync.set_calibration(calibrate);
+    if calibrate || live_input {
+        player.play();
+    }
+
+    if loop_secs.is_some() {
+        player.set_loop(loop_secs);
+    }
+    if loop_rows.is_some() {
+        sync.set_loop_rows(&mut player, loop_rows);
+    }
 
     run(size, scale, player, sync, disp)?;
 
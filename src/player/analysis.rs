@@ -0,0 +1,170 @@
+use super::{beat::BeatGrid, levels::StereoLevels, spectrum::Band};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+/// Cache file in resources
+pub const CACHE_FILE: &str = "analysis.bin";
+// Increment when the analysis changes, so that old caches get recomputed
+const CACHE_VERSION: u32 = 3;
+
+/// Audio analysis results at a moment in the track
+#[derive(Clone, Copy, Default, Debug, Serialize, Deserialize)]
+pub struct AnalysisFrame {
+    /// Bass energy, see [`Player::bass_psd`](super::Player::bass_psd)
+    pub bass: f32,
+    /// Mono downmix band magnitudes in the order of [`Band::ALL`]
+    pub bands: [f32; Band::ALL.len()],
+    /// RMS level of all channels
+    pub rms: f32,
+    /// Largest absolute sample value of any channel
+    pub peak: f32,
+    /// Normalized onset strength, from 0 to 1
+    pub onset: f32,
+    pub levels: StereoLevels,
+}
+
+impl AnalysisFrame {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        Self {
+            bass: lerp(self.bass, other.bass),
+            bands: std::array::from_fn(|i| lerp(self.bands[i], other.bands[i])),
+            rms: lerp(self.rms, other.rms),
+            peak: lerp(self.peak, other.peak),
+            onset: lerp(self.onset, other.onset),
+            levels: StereoLevels {
+                left: lerp(self.levels.left, other.levels.left),
+                right: lerp(self.levels.right, other.levels.right),
+                width: lerp(self.levels.width, other.levels.width),
+            },
+        }
+    }
+}
+
+/// Analysis frames computed at a fixed rate over the whole track
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AnalysisTable {
+    rate: f32,
+    frames: Vec<AnalysisFrame>,
+}
+
+impl AnalysisTable {
+    /// Frames per second
+    pub const RATE: f32 = 100.;
+
+    pub fn new(frames: Vec<AnalysisFrame>) -> Self {
+        Self {
+            rate: Self::RATE,
+            frames,
+        }
+    }
+
+    pub fn rate(&self) -> f32 {
+        self.rate
+    }
+
+    pub fn frames(&self) -> &[AnalysisFrame] {
+        &self.frames
+    }
+
+    /// Get interpolated analysis results, zero outside the track
+    pub fn get(&self, at_secs: f32) -> AnalysisFrame {
+        let pos = at_secs * self.rate;
+        let i = pos.floor() as usize;
+        match (pos >= 0., self.frames.get(i), self.frames.get(i + 1)) {
+            (true, Some(a), Some(b)) => a.lerp(b, pos.fract()),
+            (true, Some(a), None) => *a,
+            _ => AnalysisFrame::default(),
+        }
+    }
+
+    /// Write all frames as comma separated values, for plotting
+    pub fn write_csv(&self, mut writer: impl Write) -> Result<()> {
+        write!(writer, "secs,bass")?;
+        for band in Band::ALL {
+            write!(writer, ",band_{}_{}", band.low, band.high)?;
+        }
+        writeln!(writer, ",rms,peak,onset,left,right,width")?;
+
+        for (i, frame) in self.frames.iter().enumerate() {
+            write!(writer, "{},{}", i as f32 / self.rate, frame.bass)?;
+            for band in frame.bands {
+                write!(writer, ",{}", band)?;
+            }
+            writeln!(
+                writer,
+                ",{},{},{},{},{},{}",
+                frame.rms,
+                frame.peak,
+                frame.onset,
+                frame.levels.left,
+                frame.levels.right,
+                frame.levels.width
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Offline analysis results for an audio file, saved to avoid analyzing on every start
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Cache {
+    version: u32,
+    source_hash: u64,
+    pub beat_grid: BeatGrid,
+    pub table: AnalysisTable,
+}
+
+impl Cache {
+    #[cfg(debug_assertions)]
+    pub fn new(source: &[u8], beat_grid: BeatGrid, table: AnalysisTable) -> Self {
+        Self {
+            version: CACHE_VERSION,
+            source_hash: Self::hash(source),
+            beat_grid,
+            table,
+        }
+    }
+
+    // FNV-1a
+    fn hash(data: &[u8]) -> u64 {
+        data.iter().fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+        })
+    }
+
+    /// Load the cache if it exists and was made from `source`
+    pub fn load(source: &[u8]) -> Option<Self> {
+        #[cfg(debug_assertions)]
+        let data = &std::fs::read(std::path::PathBuf::from(crate::RESOURCES_PATH).join(CACHE_FILE))
+            .ok()?;
+        #[cfg(not(debug_assertions))]
+        let data = crate::RESOURCES_DIR.get_file(CACHE_FILE)?.contents();
+
+        let cache: Self = match bincode::deserialize(data) {
+            Ok(cache) => cache,
+            Err(e) => {
+                log::warn!("Cannot read {}: {}", CACHE_FILE, e);
+                return None;
+            }
+        };
+        if cache.version != CACHE_VERSION || cache.source_hash != Self::hash(source) {
+            log::info!("{} is out of date", CACHE_FILE);
+            return None;
+        }
+        Some(cache)
+    }
+
+    #[cfg(debug_assertions)]
+    pub fn save(&self) -> Result<()> {
+        use anyhow::Context;
+
+        let path = std::path::PathBuf::from(crate::RESOURCES_PATH).join(CACHE_FILE);
+        log::info!("Saving {}", path.display());
+        let file = std::fs::File::create(&path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        bincode::serialize_into(std::io::BufWriter::new(file), self)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
@@ -0,0 +1,233 @@
+use super::{
+    decoder::Decoder,
+    levels::ChannelMode,
+    spectrum::{Analyzer, WindowFunction},
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+// Analysis FFT size and hop in frames
+const SIZE: usize = 1024;
+const HOP: usize = 512;
+// Upper limit of bins which are considered for kick detection
+const KICK_HZ: f32 = 150.;
+// Tempo search range
+const MIN_BPM: f32 = 60.;
+const MAX_BPM: f32 = 200.;
+// Tempos near this are preferred when the envelope is ambiguous
+const PREFERRED_BPM: f32 = 120.;
+const BEATS_PER_BAR: usize = 4;
+// Kicks closer than this to the previous one are ignored
+const MIN_KICK_INTERVAL: f32 = 0.1;
+
+/// Per-frame beat values
+#[derive(Clone, Copy, Default, Debug)]
+pub struct BeatInfo {
+    /// Position within the current beat, from 0 to 1
+    pub beat_phase: f32,
+    /// Position within the current bar, from 0 to 1
+    pub bar_phase: f32,
+    /// Normalized onset strength, from 0 to 1
+    pub onset_strength: f32,
+    /// Seconds since the last kick, or since the start if there hasn't been a kick yet
+    pub since_kick: f32,
+}
+
+/// Beat grid and onset envelope computed from a whole track
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BeatGrid {
+    hop_secs: f32,
+    // Time of the first value in the envelopes
+    start_secs: f32,
+    onsets: Vec<f32>,
+    kicks: Vec<f32>,
+    beat_secs: f32,
+    first_beat: f32,
+    first_bar: f32,
+}
+
+fn compress(magnitude: f32) -> f32 {
+    (1. + 100. * magnitude).ln()
+}
+
+/// Subtract local mean to leave only peaks and normalize to 0..1
+fn normalize(envelope: &mut [f32]) {
+    const RADIUS: usize = 8;
+    let means: Vec<f32> = (0..envelope.len())
+        .map(|i| {
+            let window = &envelope[i.saturating_sub(RADIUS)..(i + RADIUS + 1).min(envelope.len())];
+            window.iter().sum::<f32>() / window.len() as f32
+        })
+        .collect();
+    for (value, mean) in envelope.iter_mut().zip(means) {
+        *value = (*value - mean).max(0.);
+    }
+
+    let max = envelope.iter().copied().fold(0., f32::max);
+    if max > 0. {
+        for value in envelope.iter_mut() {
+            *value /= max;
+        }
+    }
+}
+
+/// Sum of envelope values at `start + k * period` for all k
+fn comb(envelope: &[f32], start: f32, period: f32) -> f32 {
+    (0..)
+        .map(|k| (start + k as f32 * period).round() as usize)
+        .take_while(|&i| i < envelope.len())
+        .map(|i| envelope[i])
+        .sum()
+}
+
+impl BeatGrid {
+    /// Decode the whole track and analyze it
+    pub fn analyze(mut decoder: Box<dyn Decoder>) -> Result<Self> {
+        let channels = usize::from(decoder.channels());
+        let sample_rate = decoder.sample_rate();
+        let mut analyzer = Analyzer::new(SIZE, WindowFunction::Hann);
+        let kick_bins = (KICK_HZ / (sample_rate as f32 / SIZE as f32)).ceil() as usize;
+
+        // Spectral flux of the whole spectrum and of kick frequencies
+        let mut onsets = Vec::new();
+        let mut kick_onsets = Vec::new();
+        let mut previous: Vec<f32> = vec![0.; SIZE / 2];
+        let mut samples = Vec::new();
+        let mut more = true;
+        while more {
+            more = decoder.read(&mut samples)?;
+            while samples.len() >= SIZE * channels {
+                analyzer.process(0, &samples[..SIZE * channels], channels, ChannelMode::Mono);
+                let flux: Vec<f32> = analyzer
+                    .magnitudes()
+                    .iter()
+                    .zip(&previous)
+                    .map(|(current, previous)| (compress(*current) - previous).max(0.))
+                    .collect();
+                onsets.push(flux.iter().sum::<f32>());
+                kick_onsets.push(flux[..kick_bins].iter().sum::<f32>());
+                for (previous, current) in previous.iter_mut().zip(analyzer.magnitudes()) {
+                    *previous = compress(*current);
+                }
+                samples.drain(..HOP * channels);
+            }
+        }
+        normalize(&mut onsets);
+        normalize(&mut kick_onsets);
+
+        let hop_secs = HOP as f32 / sample_rate as f32;
+        let start_secs = SIZE as f32 / 2. / sample_rate as f32;
+
+        // Estimate tempo from autocorrelation of the onset envelope
+        let hops_per_minute = 60. / hop_secs;
+        let min_lag = (hops_per_minute / MAX_BPM).floor() as usize;
+        let max_lag = (hops_per_minute / MIN_BPM).ceil() as usize;
+        let autocorrelation: Vec<f32> = (0..=max_lag)
+            .map(|lag| {
+                let bpm = hops_per_minute / lag as f32;
+                let weight = (-0.5 * (bpm / PREFERRED_BPM).log2().powi(2)).exp();
+                weight
+                    * onsets
+                        .iter()
+                        .zip(onsets.iter().skip(lag))
+                        .map(|(a, b)| a * b)
+                        .sum::<f32>()
+            })
+            .collect();
+        let lag = (min_lag.max(1)..=max_lag)
+            .max_by(|a, b| autocorrelation[*a].total_cmp(&autocorrelation[*b]))
+            .unwrap_or(1);
+
+        // Refine period and find the beat phase which line up best with onsets over the whole
+        // track, small errors in the period add up to a lot of drift by the end
+        let (period, phase) = (0..=200)
+            .map(|step| (lag as f32 - 1.) + step as f32 / 100.)
+            .map(|period| {
+                (0..(period * 2.) as usize)
+                    .map(|half_hops| half_hops as f32 / 2.)
+                    .map(|phase| (period, phase, comb(&onsets, phase, period)))
+                    .max_by(|a, b| a.2.total_cmp(&b.2))
+                    .unwrap_or((period, 0., 0.))
+            })
+            .max_by(|a, b| a.2.total_cmp(&b.2))
+            .map(|(period, phase, _)| (period, phase))
+            .unwrap_or((lag as f32, 0.));
+
+        // Bars start on the beat with the most kicks
+        let bar_beat = (0..BEATS_PER_BAR)
+            .max_by(|a, b| {
+                let bar = period * BEATS_PER_BAR as f32;
+                comb(&kick_onsets, phase + *a as f32 * period, bar).total_cmp(&comb(
+                    &kick_onsets,
+                    phase + *b as f32 * period,
+                    bar,
+                ))
+            })
+            .unwrap_or(0);
+
+        // Pick kicks from peaks in the kick onset envelope
+        let mean = kick_onsets.iter().sum::<f32>() / kick_onsets.len().max(1) as f32;
+        let deviation = (kick_onsets.iter().map(|x| (x - mean).powi(2)).sum::<f32>()
+            / kick_onsets.len().max(1) as f32)
+            .sqrt();
+        let threshold = mean + 1.5 * deviation;
+        let mut kicks: Vec<f32> = Vec::new();
+        for i in 1..kick_onsets.len().saturating_sub(1) {
+            let secs = start_secs + i as f32 * hop_secs;
+            if kick_onsets[i] > threshold
+                && kick_onsets[i] >= kick_onsets[i - 1]
+                && kick_onsets[i] > kick_onsets[i + 1]
+                && !matches!(kicks.last(), Some(last) if secs - last < MIN_KICK_INTERVAL)
+            {
+                kicks.push(secs);
+            }
+        }
+
+        let beat_secs = period * hop_secs;
+        let first_beat = start_secs + phase * hop_secs;
+        log::info!(
+            "Detected {:.2} BPM, first beat at {:.3}s, {} kicks",
+            60. / beat_secs,
+            first_beat,
+            kicks.len()
+        );
+
+        Ok(Self {
+            hop_secs,
+            start_secs,
+            onsets,
+            kicks,
+            beat_secs,
+            first_beat,
+            first_bar: first_beat + bar_beat as f32 * beat_secs,
+        })
+    }
+
+    pub fn bpm(&self) -> f32 {
+        60. / self.beat_secs
+    }
+
+    pub fn beat_info(&self, secs: f32) -> BeatInfo {
+        // Linearly interpolate the onset envelope
+        let x = ((secs - self.start_secs) / self.hop_secs).max(0.);
+        let i = x as usize;
+        let onset_strength = match (self.onsets.get(i), self.onsets.get(i + 1)) {
+            (Some(a), Some(b)) => a + (b - a) * x.fract(),
+            (Some(a), None) => *a,
+            _ => 0.,
+        };
+
+        let kicks_before = self.kicks.partition_point(|kick| *kick <= secs);
+
+        BeatInfo {
+            beat_phase: ((secs - self.first_beat) / self.beat_secs).rem_euclid(1.),
+            bar_phase: ((secs - self.first_bar) / (self.beat_secs * BEATS_PER_BAR as f32))
+                .rem_euclid(1.),
+            onset_strength,
+            since_kick: secs
+                - kicks_before
+                    .checked_sub(1)
+                    .map_or(0., |last| self.kicks[last]),
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use super::ring::Ring;
+use anyhow::{Context, Result};
+use cpal::traits::{DeviceTrait, StreamTrait};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+// Samples converted at a time in the input callback
+const CHUNK: usize = 256;
+
+/// Samples from the input callback, which pushes them without locking
+struct Buffer {
+    ring: Ring,
+    // Frames captured since start
+    frames: AtomicU64,
+}
+
+/// Latest audio from an input device, for analysis on the render thread
+pub struct Capture {
+    _stream: cpal::Stream,
+    buffer: Arc<Buffer>,
+    sample_rate: u32,
+    channels: u8,
+    // Latest samples taken from the ring
+    samples: VecDeque<i16>,
+    capacity: usize,
+    latest: Vec<i16>,
+}
+
+impl Capture {
+    /// Start capturing, keeping the latest `capacity` frames
+    pub fn start(device: &cpal::Device, capacity: usize) -> Result<Self> {
+        let device_name = device.name().unwrap_or_else(|_| "(unknown)".into());
+        let supported_config = device.default_input_config().with_context(|| {
+            format!("Failed to query parameters of audio device {}", device_name)
+        })?;
+        let format = supported_config.sample_format();
+        let config: cpal::StreamConfig = supported_config.into();
+        let channels =
+            u8::try_from(config.channels).context("Audio device has too many channels")?;
+        log::info!(
+            "Capturing {}Hz {} channel audio from {}",
+            config.sample_rate.0,
+            channels,
+            device_name
+        );
+
+        let capacity = capacity * usize::from(channels);
+        let buffer = Arc::new(Buffer {
+            ring: Ring::new(capacity),
+            frames: AtomicU64::new(0),
+        });
+        let stream = match format {
+            cpal::SampleFormat::I16 => Self::build::<i16>(device, &config, &buffer)?,
+            cpal::SampleFormat::U16 => Self::build::<u16>(device, &config, &buffer)?,
+            cpal::SampleFormat::F32 => Self::build::<f32>(device, &config, &buffer)?,
+        };
+        stream
+            .play()
+            .context("Failed to start audio input stream")?;
+
+        Ok(Self {
+            _stream: stream,
+            buffer,
+            sample_rate: config.sample_rate.0,
+            channels,
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+            latest: Vec::with_capacity(capacity),
+        })
+    }
+
+    fn build<T: cpal::Sample>(
+        device: &cpal::Device,
+        config: &cpal::StreamConfig,
+        buffer: &Arc<Buffer>,
+    ) -> Result<cpal::Stream> {
+        let channels = usize::from(config.channels);
+        let buffer = buffer.clone();
+        device
+            .build_input_stream(
+                config,
+                move |data: &[T], _: &cpal::InputCallbackInfo| {
+                    // Samples which don't fit are dropped until the render thread catches up
+                    let mut chunk = [0; CHUNK];
+                    for samples in data.chunks(CHUNK) {
+                        for (value, sample) in chunk.iter_mut().zip(samples) {
+                            *value = sample.to_i16();
+                        }
+                        buffer.ring.push(&chunk[..samples.len()]);
+                    }
+                    buffer
+                        .frames
+                        .fetch_add((data.len() / channels) as u64, Ordering::Relaxed);
+                },
+                |err| log::error!("{}", err),
+            )
+            .context("Failed to build audio input stream")
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    /// Frames captured since start, changes whenever new audio arrives
+    pub fn position(&self) -> u64 {
+        self.buffer.frames.load(Ordering::Relaxed)
+    }
+
+    /// Get up to `frames` most recently captured frames of interleaved samples
+    pub fn latest(&mut self, frames: usize) -> &[i16] {
+        let samples = &mut self.samples;
+        self.buffer
+            .ring
+            .pop(usize::MAX, |_, value| samples.push_back(value));
+        let excess = samples.len().saturating_sub(self.capacity);
+        samples.drain(..excess);
+
+        let len = samples.len();
+        let start = len.saturating_sub(frames * usize::from(self.channels));
+        self.latest.clear();
+        self.latest.extend(samples.range(start..));
+        &self.latest
+    }
+}
@@ -0,0 +1,77 @@
+use super::decoder::Decoder;
+use anyhow::Result;
+
+const SAMPLE_RATE: u32 = 48000;
+const CHANNELS: u8 = 2;
+const LEN_SECS: u64 = 60;
+// Frames decoded per read
+const BLOCK: u64 = 1024;
+// Click frequencies, first beat of each bar is accented
+const CLICK_HZ: f32 = 1000.;
+const ACCENT_HZ: f32 = 2000.;
+const CLICK_SECS: f32 = 0.02;
+
+/// Seconds between clicks
+pub const CLICK_INTERVAL: f32 = 0.5;
+/// Clicks per bar
+pub const CLICKS_PER_BAR: u32 = 4;
+
+/// Generated metronome track for latency calibration
+pub struct ClickTrack {
+    position: u64,
+}
+
+impl ClickTrack {
+    pub fn new() -> Self {
+        Self { position: 0 }
+    }
+
+    fn sample(frame: u64) -> i16 {
+        let secs = frame as f32 / SAMPLE_RATE as f32;
+        let click = (secs / CLICK_INTERVAL) as u32;
+        let t = secs - click as f32 * CLICK_INTERVAL;
+        if t >= CLICK_SECS {
+            return 0;
+        }
+        let beat_in_bar = click % CLICKS_PER_BAR;
+        let hz = if beat_in_bar == 0 {
+            ACCENT_HZ
+        } else {
+            CLICK_HZ
+        };
+        let envelope = (-t * 5. / CLICK_SECS).exp();
+        ((t * hz * std::f32::consts::TAU).sin() * envelope * 0.5 * i16::MAX as f32) as i16
+    }
+}
+
+impl Decoder for ClickTrack {
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn channels(&self) -> u8 {
+        CHANNELS
+    }
+
+    fn len_frames(&self) -> u64 {
+        LEN_SECS * u64::from(SAMPLE_RATE)
+    }
+
+    fn seek(&mut self, frame: u64) -> Result<()> {
+        self.position = frame.min(self.len_frames());
+        Ok(())
+    }
+
+    fn read(&mut self, out: &mut Vec<i16>) -> Result<bool> {
+        let end = (self.position + BLOCK).min(self.len_frames());
+        for frame in self.position..end {
+            out.extend_from_slice(&[Self::sample(frame); CHANNELS as usize]);
+        }
+        self.position = end;
+        Ok(end < self.len_frames())
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn Decoder>> {
+        Ok(Box::new(Self::new()))
+    }
+}
@@ -0,0 +1,221 @@
+use super::stretch::Stretch;
+use std::collections::VecDeque;
+
+const SQRT_HALF: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// Mixing gains from each input channel to each output channel, `[output][input]`
+fn channel_matrix(from: usize, to: usize) -> Vec<Vec<f32>> {
+    let mut matrix = vec![vec![0.; from]; to];
+    match (from, to) {
+        (from, to) if from == to => {
+            for (i, row) in matrix.iter_mut().enumerate() {
+                row[i] = 1.;
+            }
+        }
+        // Average everything to mono
+        (from, 1) => matrix[0].fill(1. / from as f32),
+        // Mono to front left and right
+        (1, _) => {
+            matrix[0][0] = 1.;
+            matrix[1][0] = 1.;
+        }
+        // 5.1 (FL, FR, C, LFE, SL, SR) to stereo, LFE is left out
+        (6, 2) => {
+            matrix[0] = vec![1., 0., SQRT_HALF, 0., SQRT_HALF, 0.];
+            matrix[1] = vec![0., 1., SQRT_HALF, 0., 0., SQRT_HALF];
+        }
+        // Otherwise keep common channels and fold extra input channels over them
+        (from, to) => {
+            for i in 0..from {
+                matrix[i % to][i] = if i < to { 1. } else { SQRT_HALF };
+            }
+        }
+    }
+    matrix
+}
+
+/// Sample rate and channel count conversion for interleaved samples
+pub struct Converter {
+    from_channels: usize,
+    to_channels: usize,
+    matrix: Vec<Vec<f32>>,
+    // Source frames per output frame at normal playback rate
+    ratio: f64,
+    // Source frames per output frame
+    step: f64,
+    // Time stretching after resampling at normal rate, when pitch is kept at other rates
+    stretch: Option<Stretch>,
+    resampled: Vec<i16>,
+    // Channel mapped frames waiting for interpolation
+    history: VecDeque<f32>,
+    // Position of the next output frame relative to history[1]
+    phase: f64,
+}
+
+impl Converter {
+    pub fn new(from_rate: u32, from_channels: u8, to_rate: u32, to_channels: u8) -> Self {
+        let (from_channels, to_channels) = (usize::from(from_channels), usize::from(to_channels));
+        let ratio = f64::from(from_rate) / f64::from(to_rate);
+        Self {
+            from_channels,
+            to_channels,
+            matrix: channel_matrix(from_channels, to_channels),
+            ratio,
+            step: ratio,
+            stretch: None,
+            resampled: Vec::new(),
+            history: VecDeque::new(),
+            phase: 0.,
+        }
+    }
+
+    /// Output channel count
+    pub fn channels(&self) -> usize {
+        self.to_channels
+    }
+
+    /// Source position in frames corresponding to an output position
+    pub fn source_frame(&self, output_frame: u64) -> f64 {
+        output_frame as f64 * self.step
+    }
+
+    /// Speed up or slow down playback by resampling, which also changes the pitch
+    /// unless `preserve_pitch` is set
+    pub fn set_rate(&mut self, rate: f64, preserve_pitch: bool) {
+        self.step = self.ratio * rate;
+        self.stretch = (preserve_pitch && rate != 1.).then(|| Stretch::new(self.to_channels, rate));
+    }
+
+    /// Restart after the source has been seeked, `phase` is the fraction of a frame
+    /// between the first next input frame and the first next output frame
+    pub fn reset(&mut self, phase: f64) {
+        self.history.clear();
+        self.phase = phase;
+        if let Some(stretch) = &mut self.stretch {
+            stretch.reset();
+        }
+    }
+
+    /// Convert `input` and append the results to `output`
+    pub fn process(&mut self, input: &[i16], output: &mut Vec<i16>) {
+        match self.stretch.take() {
+            Some(mut stretch) => {
+                let mut resampled = std::mem::take(&mut self.resampled);
+                resampled.clear();
+                self.resample(input, &mut resampled, self.ratio);
+                stretch.process(&resampled, output);
+                self.resampled = resampled;
+                self.stretch = Some(stretch);
+            }
+            None => self.resample(input, output, self.step),
+        }
+    }
+
+    fn resample(&mut self, input: &[i16], output: &mut Vec<i16>, step: f64) {
+        // Fast path for matching formats
+        if step == 1. && self.from_channels == self.to_channels {
+            output.extend_from_slice(input);
+            return;
+        }
+
+        for frame in input.chunks(self.from_channels) {
+            // Repeat the first frame so that there's a frame before the first output position
+            let repeat = if self.history.is_empty() { 2 } else { 1 };
+            for _ in 0..repeat {
+                self.history.extend(self.matrix.iter().map(|row| {
+                    row.iter()
+                        .zip(frame)
+                        .map(|(gain, sample)| gain * f32::from(*sample))
+                        .sum::<f32>()
+                }));
+            }
+        }
+
+        // Cubic Hermite interpolation between history frames 1 and 2
+        while self.history.len() / self.to_channels >= 4 {
+            let t = self.phase as f32;
+            for channel in 0..self.to_channels {
+                let [y0, y1, y2, y3] =
+                    [0, 1, 2, 3].map(|i| self.history[i * self.to_channels + channel]);
+                let c1 = 0.5 * (y2 - y0);
+                let c2 = y0 - 2.5 * y1 + 2. * y2 - 0.5 * y3;
+                let c3 = 0.5 * (y3 - y0) + 1.5 * (y1 - y2);
+                let value = ((c3 * t + c2) * t + c1) * t + y1;
+                output.push(value.clamp(i16::MIN.into(), i16::MAX.into()) as i16);
+            }
+
+            self.phase += step;
+            let consumed = self.phase.floor();
+            self.phase -= consumed;
+            let consumed = (consumed as usize * self.to_channels).min(self.history.len());
+            self.history.drain(..consumed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(converter: &mut Converter, input: &[i16]) -> Vec<i16> {
+        let mut output = Vec::new();
+        // Feed in packets like a decoder would
+        for packet in input.chunks(1000 * converter.from_channels) {
+            converter.process(packet, &mut output);
+        }
+        output
+    }
+
+    fn sine(rate: u32, hz: f32, amplitude: f32, frames: usize) -> Vec<i16> {
+        (0..frames)
+            .map(|i| {
+                (amplitude * (i as f32 / rate as f32 * hz * std::f32::consts::TAU).sin()) as i16
+            })
+            .collect()
+    }
+
+    #[test]
+    fn resamples_44100_to_48000() {
+        let mut converter = Converter::new(44100, 1, 48000, 1);
+        let output = convert(&mut converter, &[10000; 44100]);
+        // Interpolation holds back a few frames until more input arrives
+        assert!((47995..=48000).contains(&output.len()), "{}", output.len());
+        assert!(output.iter().all(|&sample| sample == 10000));
+
+        let mut converter = Converter::new(44100, 1, 48000, 1);
+        let output = convert(&mut converter, &sine(44100, 1000., 16000., 44100));
+        let rms = (output.iter().map(|&x| f32::from(x).powi(2)).sum::<f32>() / output.len() as f32)
+            .sqrt();
+        assert!((rms / (16000. * SQRT_HALF) - 1.).abs() < 0.01, "{}", rms);
+        let crossings = output
+            .windows(2)
+            .filter(|pair| pair[0] < 0 && pair[1] >= 0)
+            .count();
+        assert!((999..=1001).contains(&crossings), "{}", crossings);
+    }
+
+    #[test]
+    fn copies_mono_to_stereo() {
+        let input: Vec<i16> = (0..100).map(|i| i * 100).collect();
+        let mut converter = Converter::new(48000, 1, 48000, 2);
+        let output = convert(&mut converter, &input);
+        assert_eq!(output.len(), (input.len() - 2) * 2);
+        for (frame, sample) in output.chunks(2).zip(&input) {
+            assert_eq!(frame, [*sample, *sample]);
+        }
+    }
+
+    #[test]
+    fn downmixes_5_1_to_stereo() {
+        let input = [1000, 2000, 3000, 4000, 5000, 6000].repeat(100);
+        let mut converter = Converter::new(48000, 6, 48000, 2);
+        let output = convert(&mut converter, &input);
+        assert_eq!(output.len(), 98 * 2);
+        let left = 1000. + SQRT_HALF * (3000. + 5000.);
+        let right = 2000. + SQRT_HALF * (3000. + 6000.);
+        for frame in output.chunks(2) {
+            assert!((f32::from(frame[0]) - left).abs() <= 1., "{:?}", frame);
+            assert!((f32::from(frame[1]) - right).abs() <= 1., "{:?}", frame);
+        }
+    }
+}
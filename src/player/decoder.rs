@@ -0,0 +1,153 @@
+#[cfg(feature = "opus")]
+use super::opus::OpusDecoder;
+use super::{
+    lossless::LosslessDecoder,
+    ogg::OggDecoder,
+    synth::SynthDecoder,
+    tracker::{Timeline, TrackerDecoder},
+};
+use anyhow::{anyhow, Result};
+use std::{path::Path, sync::Arc};
+
+/// Seekable audio decoder producing interleaved i16 samples
+pub trait Decoder: Send {
+    fn sample_rate(&self) -> u32;
+
+    fn channels(&self) -> u8;
+
+    fn len_frames(&self) -> u64;
+
+    /// Seek so that the next decoded samples start exactly at `frame`
+    fn seek(&mut self, frame: u64) -> Result<()>;
+
+    /// Append the next decoded interleaved samples to `out`
+    ///
+    /// # Return value
+    ///
+    /// Returns false when the end of stream has been reached
+    fn read(&mut self, out: &mut Vec<i16>) -> Result<bool>;
+
+    /// Create another decoder for the same data, starting from the beginning
+    fn try_clone(&self) -> Result<Box<dyn Decoder>>;
+
+    /// Where tracker rows are played, for tracker modules
+    fn timeline(&self) -> Option<Arc<Timeline>> {
+        None
+    }
+}
+
+/// Track decoded to memory, so that analysis passes over it don't decode it again
+pub struct Decoded {
+    samples: Arc<[i16]>,
+    sample_rate: u32,
+    channels: u8,
+    // Interleaved sample position of the next read
+    position: usize,
+}
+
+impl Decoded {
+    // Frames returned per read
+    const BLOCK: usize = 4096;
+
+    /// Decode the whole track from the start
+    pub fn new(mut decoder: Box<dyn Decoder>) -> Result<Self> {
+        let mut samples = Vec::new();
+        while decoder.read(&mut samples)? {}
+        Ok(Self {
+            samples: samples.into(),
+            sample_rate: decoder.sample_rate(),
+            channels: decoder.channels(),
+            position: 0,
+        })
+    }
+}
+
+impl Decoder for Decoded {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    fn len_frames(&self) -> u64 {
+        (self.samples.len() / usize::from(self.channels)) as u64
+    }
+
+    fn seek(&mut self, frame: u64) -> Result<()> {
+        self.position = (frame as usize * usize::from(self.channels)).min(self.samples.len());
+        Ok(())
+    }
+
+    fn read(&mut self, out: &mut Vec<i16>) -> Result<bool> {
+        let end =
+            (self.position + Self::BLOCK * usize::from(self.channels)).min(self.samples.len());
+        out.extend_from_slice(&self.samples[self.position..end]);
+        self.position = end;
+        Ok(end < self.samples.len())
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn Decoder>> {
+        Ok(Box::new(Self {
+            samples: self.samples.clone(),
+            position: 0,
+            ..*self
+        }))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Format {
+    Vorbis,
+    Opus,
+    Wav,
+    Flac,
+    Xm,
+    Synth,
+}
+
+impl Format {
+    /// Detect format from magic bytes, or from file extension if they're not recognized
+    fn detect(path: &Path, data: &[u8]) -> Option<Self> {
+        // Ogg codec is identified by the first packet, which starts right after the first
+        // page header when it has a single segment
+        match data {
+            [b'O', b'g', b'g', b'S', ..] if data.get(28..36) == Some(b"OpusHead") => {
+                Some(Self::Opus)
+            }
+            [b'O', b'g', b'g', b'S', ..] => Some(Self::Vorbis),
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => Some(Self::Wav),
+            [b'f', b'L', b'a', b'C', ..] => Some(Self::Flac),
+            _ if data.starts_with(b"Extended Module: ") => Some(Self::Xm),
+            _ => match path.extension()?.to_str()?.to_lowercase().as_str() {
+                "ogg" | "oga" => Some(Self::Vorbis),
+                "opus" => Some(Self::Opus),
+                "wav" => Some(Self::Wav),
+                "flac" => Some(Self::Flac),
+                "xm" => Some(Self::Xm),
+                "song" => Some(Self::Synth),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// Pick a decoder for a file's contents
+pub fn open(path: &Path, data: Arc<[u8]>) -> Result<Box<dyn Decoder>> {
+    match Format::detect(path, &data) {
+        Some(Format::Vorbis) => Ok(Box::new(OggDecoder::new(data)?)),
+        #[cfg(feature = "opus")]
+        Some(Format::Opus) => Ok(Box::new(OpusDecoder::new(data)?)),
+        #[cfg(not(feature = "opus"))]
+        Some(Format::Opus) => Err(anyhow!(
+            "{} is Opus, which needs building with --features opus",
+            path.display()
+        )),
+        Some(Format::Wav) => Ok(Box::new(LosslessDecoder::new(data, "wav")?)),
+        Some(Format::Flac) => Ok(Box::new(LosslessDecoder::new(data, "flac")?)),
+        Some(Format::Xm) => Ok(Box::new(TrackerDecoder::new(&data)?)),
+        Some(Format::Synth) => Ok(Box::new(SynthDecoder::new(&data)?)),
+        None => Err(anyhow!("{} has an unknown audio format", path.display())),
+    }
+}
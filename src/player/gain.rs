@@ -0,0 +1,227 @@
+use super::{seqlock::SeqLock, stream::wrap};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+// Time constant of volume changes in seconds, avoids clicks
+const SMOOTHING_SECS: f32 = 0.01;
+
+/// Per frame smoothing coefficient for volume changes at `sample_rate`
+pub fn smoothing(sample_rate: u32) -> f32 {
+    1. - (-1. / (SMOOTHING_SECS * sample_rate as f32)).exp()
+}
+
+// Marks a missing range in the envelope's atomics
+const NONE: u64 = u64::MAX;
+
+/// Linear ramp between interleaved output sample positions
+#[derive(Clone, Copy)]
+struct Ramp {
+    start: usize,
+    end: usize,
+}
+
+impl Ramp {
+    /// Progress from 0 before the start to 1 after the end
+    fn progress(self, pos: usize) -> f32 {
+        if pos <= self.start {
+            0.
+        } else if pos >= self.end {
+            1.
+        } else {
+            (pos - self.start) as f32 / (self.end - self.start) as f32
+        }
+    }
+}
+
+fn encode(range: Option<(usize, usize)>) -> [u64; 2] {
+    range.map_or([NONE; 2], |(start, end)| [start as u64, end as u64])
+}
+
+fn decode(start: u64, end: u64) -> Option<(usize, usize)> {
+    (start != NONE).then_some((start as usize, end as usize))
+}
+
+/// Fades and the loop region which they repeat in
+#[derive(Clone, Copy, Default)]
+struct Envelope {
+    fade_in: Option<Ramp>,
+    fade_out: Option<Ramp>,
+    loop_region: Option<(usize, usize)>,
+}
+
+impl Envelope {
+    fn value(&self, pos: usize) -> f32 {
+        let pos = wrap(pos, self.loop_region);
+        self.fade_in.map_or(1., |ramp| ramp.progress(pos))
+            * self.fade_out.map_or(1., |ramp| 1. - ramp.progress(pos))
+    }
+}
+
+/// Master volume, mute and fades, set by the render thread and read by the audio callback
+/// without locking
+pub struct GainControl {
+    // Bits of an f32
+    volume: AtomicU32,
+    muted: AtomicBool,
+    // Fade in, fade out and loop region as start and end positions
+    envelope: SeqLock<6>,
+}
+
+impl Default for GainControl {
+    fn default() -> Self {
+        Self {
+            volume: AtomicU32::new(1f32.to_bits()),
+            muted: AtomicBool::new(false),
+            envelope: SeqLock::new([NONE; 6]),
+        }
+    }
+}
+
+impl GainControl {
+    pub fn volume(&self) -> f32 {
+        f32::from_bits(self.volume.load(Ordering::Relaxed))
+    }
+
+    pub fn set_volume(&self, volume: f32) {
+        self.volume
+            .store(volume.max(0.).to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted.load(Ordering::Relaxed)
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        self.muted.store(muted, Ordering::Relaxed);
+    }
+
+    /// Set fade in and fade out ranges and the loop region in interleaved output sample positions
+    pub fn set_envelope(
+        &self,
+        fade_in: Option<(usize, usize)>,
+        fade_out: Option<(usize, usize)>,
+        loop_region: Option<(usize, usize)>,
+    ) {
+        let [a, b] = encode(fade_in);
+        let [c, d] = encode(fade_out);
+        let [e, f] = encode(loop_region);
+        self.envelope.write([a, b, c, d, e, f]);
+    }
+}
+
+/// Gain applied by the audio callback, following its [`GainControl`]
+pub struct Gain {
+    // Gain being applied, follows volume and mute smoothly
+    current: f32,
+    // Latest envelope read without a write in progress
+    envelope: Envelope,
+}
+
+impl Default for Gain {
+    fn default() -> Self {
+        Self {
+            current: 1.,
+            envelope: Envelope::default(),
+        }
+    }
+}
+
+impl Gain {
+    /// Scale interleaved samples starting from output sample position `pos`
+    pub fn apply<T: cpal::Sample>(
+        &mut self,
+        control: &GainControl,
+        pos: usize,
+        channels: usize,
+        smoothing: f32,
+        data: &mut [T],
+    ) {
+        let target = if control.is_muted() {
+            0.
+        } else {
+            control.volume()
+        };
+        if let Some([a, b, c, d, e, f]) = control.envelope.try_read() {
+            let ramp = |start, end| decode(start, end).map(|(start, end)| Ramp { start, end });
+            self.envelope = Envelope {
+                fade_in: ramp(a, b),
+                fade_out: ramp(c, d),
+                loop_region: decode(e, f),
+            };
+        }
+
+        // Nothing to do at unity gain
+        if self.current == 1.
+            && target == 1.
+            && self.envelope.fade_in.is_none()
+            && self.envelope.fade_out.is_none()
+        {
+            return;
+        }
+
+        for (i, frame) in data.chunks_mut(channels).enumerate() {
+            self.current += (target - self.current) * smoothing;
+            if (target - self.current).abs() < 1e-4 {
+                self.current = target;
+            }
+
+            let gain = self.current * self.envelope.value(pos + i * channels);
+            for sample in frame {
+                *sample = cpal::Sample::from(&(sample.to_f32() * gain));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48000;
+
+    /// Gains applied to a mono signal of ones starting at `pos`
+    fn apply(gain: &mut Gain, control: &GainControl, pos: usize, len: usize) -> Vec<f32> {
+        let mut data = vec![1f32; len];
+        gain.apply(control, pos, 1, smoothing(RATE), &mut data);
+        data
+    }
+
+    #[test]
+    fn follows_fades() {
+        let control = GainControl::default();
+        control.set_envelope(Some((0, 1000)), Some((9000, 10000)), None);
+        let data = apply(&mut Gain::default(), &control, 0, 10001);
+        for (pos, value) in [
+            (0, 0.),
+            (500, 0.5),
+            (1000, 1.),
+            (5000, 1.),
+            (9000, 1.),
+            (9500, 0.5),
+            (10000, 0.),
+        ] {
+            assert!((data[pos] - value).abs() < 1e-6, "{}: {}", pos, data[pos]);
+        }
+    }
+
+    #[test]
+    fn mutes_during_fade() {
+        let control = GainControl::default();
+        control.set_envelope(Some((0, RATE as usize)), None, None);
+        let mut gain = Gain::default();
+        apply(&mut gain, &control, 0, 12000);
+
+        // Volume goes smoothly to silence while the fade goes on
+        control.set_muted(true);
+        let data = apply(&mut gain, &control, 12000, 12000);
+        assert!(data[0] > 0.2 && data[0] < 0.25, "{}", data[0]);
+        assert!(data.windows(2).all(|pair| pair[1] <= pair[0]));
+        assert_eq!(data[data.len() - 1], 0.);
+
+        // And comes back to where the fade is by now
+        control.set_muted(false);
+        let data = apply(&mut gain, &control, 24000, 12000);
+        assert!(data[0] < 0.01, "{}", data[0]);
+        let end = data[data.len() - 1];
+        assert!((end - 35999. / RATE as f32).abs() < 1e-3, "{}", end);
+    }
+}
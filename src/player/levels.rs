@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+
+/// Which signal of a multichannel track is analyzed
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ChannelMode {
+    /// First channel only
+    First,
+    /// Average of all channels
+    Mono,
+    /// Single channel by index, eg. 0 for left and 1 for right
+    Channel(usize),
+    /// Average of the first two channels
+    Mid,
+    /// Half of the difference between the first two channels
+    Side,
+}
+
+impl ChannelMode {
+    pub const LEFT: Self = Self::Channel(0);
+    pub const RIGHT: Self = Self::Channel(1);
+
+    /// Pick a value from a frame of interleaved samples, scaled to -1..1
+    pub fn sample(self, frame: &[i16]) -> f32 {
+        let get = |channel: usize| frame[channel.min(frame.len() - 1)] as f32 / i16::MAX as f32;
+        match self {
+            Self::First => get(0),
+            Self::Mono => (0..frame.len()).map(get).sum::<f32>() / frame.len() as f32,
+            Self::Channel(channel) => get(channel),
+            Self::Mid => (get(0) + get(1)) / 2.,
+            Self::Side => (get(0) - get(1)) / 2.,
+        }
+    }
+}
+
+/// Loudness of the left and right channels
+#[derive(Clone, Copy, Default, Debug, Serialize, Deserialize)]
+pub struct StereoLevels {
+    /// RMS level of the left channel
+    pub left: f32,
+    /// RMS level of the right channel
+    pub right: f32,
+    /// Side energy relative to total energy, 0 for mono and 1 for opposite phase channels
+    pub width: f32,
+}
+
+impl StereoLevels {
+    pub fn from_samples(samples: &[i16], channels: usize) -> Self {
+        let frames = (samples.len() / channels).max(1) as f32;
+        let mean_square = |mode: ChannelMode| {
+            samples
+                .chunks(channels)
+                .map(|frame| mode.sample(frame).powi(2))
+                .sum::<f32>()
+                / frames
+        };
+
+        let mid = mean_square(ChannelMode::Mid);
+        let side = mean_square(ChannelMode::Side);
+        Self {
+            left: mean_square(ChannelMode::LEFT).sqrt(),
+            right: mean_square(ChannelMode::RIGHT).sqrt(),
+            width: if mid + side > 0. {
+                side / (mid + side)
+            } else {
+                0.
+            },
+        }
+    }
+}
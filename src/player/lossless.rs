@@ -0,0 +1,139 @@
+use super::decoder::Decoder;
+use anyhow::{Context, Result};
+use std::{io::Cursor, sync::Arc};
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::DecoderOptions,
+    errors::Error,
+    formats::{FormatOptions, FormatReader, SeekMode, SeekTo},
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+};
+
+/// WAV and FLAC decoder
+pub struct LosslessDecoder {
+    data: Arc<[u8]>,
+    extension: &'static str,
+    reader: Box<dyn FormatReader>,
+    decoder: Box<dyn symphonia::core::codecs::Decoder>,
+    track_id: u32,
+    sample_rate: u32,
+    channels: u8,
+    len_frames: u64,
+    buffer: Option<SampleBuffer<i16>>,
+    // Decoded frames before this position are discarded
+    target: u64,
+}
+
+impl LosslessDecoder {
+    pub fn new(data: Arc<[u8]>, extension: &'static str) -> Result<Self> {
+        let stream =
+            MediaSourceStream::new(Box::new(Cursor::new(data.clone())), Default::default());
+        let reader = symphonia::default::get_probe()
+            .format(
+                Hint::new().with_extension(extension),
+                stream,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )
+            .with_context(|| format!("Failed to read {} headers", extension))?
+            .format;
+
+        let track = reader
+            .default_track()
+            .with_context(|| format!("No audio track in {} file", extension))?;
+        let params = &track.codec_params;
+        let decoder = symphonia::default::get_codecs()
+            .make(params, &DecoderOptions::default())
+            .with_context(|| format!("Unsupported {} codec", extension))?;
+
+        Ok(Self {
+            extension,
+            track_id: track.id,
+            sample_rate: params.sample_rate.context("Unknown sample rate")?,
+            channels: params
+                .channels
+                .map(|channels| channels.count())
+                .and_then(|count| u8::try_from(count).ok())
+                .context("Unknown channel count")?,
+            len_frames: params.n_frames.context("Unknown track length")?,
+            data,
+            reader,
+            decoder,
+            buffer: None,
+            target: 0,
+        })
+    }
+}
+
+impl Decoder for LosslessDecoder {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    fn len_frames(&self) -> u64 {
+        self.len_frames
+    }
+
+    fn seek(&mut self, frame: u64) -> Result<()> {
+        self.reader
+            .seek(
+                SeekMode::Accurate,
+                SeekTo::TimeStamp {
+                    ts: frame,
+                    track_id: self.track_id,
+                },
+            )
+            .with_context(|| format!("Failed to seek {} stream", self.extension))?;
+        self.decoder.reset();
+        self.target = frame;
+        Ok(())
+    }
+
+    fn read(&mut self, out: &mut Vec<i16>) -> Result<bool> {
+        let channels = usize::from(self.channels);
+        loop {
+            let packet = match self.reader.next_packet() {
+                Ok(packet) => packet,
+                Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    return Ok(false)
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| format!("Failed to read {}", self.extension))
+                }
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            let decoded = self
+                .decoder
+                .decode(&packet)
+                .with_context(|| format!("Failed to decode {}", self.extension))?;
+            let frames = decoded.frames();
+            let buffer = match &mut self.buffer {
+                Some(buffer) if buffer.capacity() >= frames * channels => buffer,
+                buffer => buffer.insert(SampleBuffer::new(
+                    decoded.capacity() as u64,
+                    *decoded.spec(),
+                )),
+            };
+            buffer.copy_interleaved_ref(decoded);
+
+            let skip = self.target.saturating_sub(packet.ts()).min(frames as u64) as usize;
+            if skip < frames {
+                out.extend_from_slice(&buffer.samples()[skip * channels..frames * channels]);
+                return Ok(true);
+            }
+        }
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn Decoder>> {
+        Ok(Box::new(Self::new(self.data.clone(), self.extension)?))
+    }
+}
@@ -0,0 +1,70 @@
+// Default envelope follower time constants in seconds
+pub const ATTACK_SECS: f32 = 0.01;
+pub const RELEASE_SECS: f32 = 0.3;
+
+/// Loudness of all channels over a short window
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Loudness {
+    /// RMS level of all channels
+    pub rms: f32,
+    /// Largest absolute sample value of any channel
+    pub peak: f32,
+}
+
+impl Loudness {
+    pub fn from_samples(samples: &[i16]) -> Self {
+        let count = samples.len().max(1) as f32;
+        let values = || {
+            samples
+                .iter()
+                .map(|&sample| sample as f32 / i16::MAX as f32)
+        };
+        Self {
+            rms: (values().map(|value| value.powi(2)).sum::<f32>() / count).sqrt(),
+            peak: values().map(f32::abs).fold(0., f32::max),
+        }
+    }
+}
+
+/// Smoothed level which rises with the attack and falls with the release time constant
+pub struct Envelope {
+    rate: f32,
+    values: Vec<f32>,
+}
+
+impl Envelope {
+    /// Follow `levels` sampled at `rate` values per second
+    pub fn follow(levels: &[f32], rate: f32, attack_secs: f32, release_secs: f32) -> Self {
+        let coefficient = |secs: f32| {
+            if secs > 0. {
+                1. - (-1. / (secs * rate)).exp()
+            } else {
+                1.
+            }
+        };
+        let (attack, release) = (coefficient(attack_secs), coefficient(release_secs));
+
+        let mut current = 0.;
+        let values = levels
+            .iter()
+            .map(|&level| {
+                let k = if level > current { attack } else { release };
+                current += (level - current) * k;
+                current
+            })
+            .collect();
+
+        Self { rate, values }
+    }
+
+    /// Get interpolated envelope value, zero outside the track
+    pub fn get(&self, at_secs: f32) -> f32 {
+        let pos = at_secs * self.rate;
+        let i = pos.floor() as usize;
+        match (pos >= 0., self.values.get(i), self.values.get(i + 1)) {
+            (true, Some(a), Some(b)) => a + (b - a) * pos.fract(),
+            (true, Some(a), None) => *a,
+            _ => 0.,
+        }
+    }
+}
@@ -0,0 +1,1215 @@
+mod analysis;
+mod beat;
+mod capture;
+mod click;
+mod convert;
+mod decoder;
+mod gain;
+mod levels;
+mod lossless;
+mod loudness;
+mod ogg;
+#[cfg(feature = "opus")]
+mod opus;
+mod ring;
+mod seqlock;
+mod spectrum;
+mod stats;
+mod stream;
+mod stretch;
+mod synth;
+mod tracker;
+
+pub use analysis::{AnalysisFrame, AnalysisTable};
+use anyhow::{anyhow, Context, Result};
+pub use beat::BeatInfo;
+pub use click::CLICK_INTERVAL;
+use convert::Converter;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use decoder::Decoder;
+pub use levels::{ChannelMode, StereoLevels};
+pub use loudness::Loudness;
+use seqlock::SeqLock;
+pub use spectrum::{Band, WindowFunction};
+pub use stats::AudioStats;
+use std::{
+    convert::TryFrom,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+pub use tracker::TrackerPosition;
+
+// Playback buffering/latency size in frames
+const BUF_SIZE: u32 = 4096;
+// Default FFT size in samples per channel, eg fft from stereo track reads double this number of i16s
+const FFT_SIZE: usize = 1024;
+// How many frames the decoder thread keeps ready ahead of playback
+const STREAM_BUF_SIZE: usize = BUF_SIZE as usize * 8;
+// How many of the latest frames are kept from live input
+const CAPTURE_BUF_SIZE: usize = BUF_SIZE as usize * 4;
+// Supported range of playback rates
+const MIN_RATE: f32 = 0.1;
+const MAX_RATE: f32 = 4.;
+// Timer lag behind audio in seconds which is corrected immediately instead of gradually
+const MAX_DRIFT: f32 = 0.25;
+// Fraction of timer error corrected on each time query
+const DRIFT_SLEW: f32 = 0.05;
+// Largest fraction of the time since the last correction which is taken back when the timer
+// is ahead of audio, so that time slows down instead of going backwards and replaying rows
+const MAX_SLOWDOWN: f32 = 0.5;
+
+/// Output position at an audio callback, and when its first sample is heard
+#[derive(Clone, Copy)]
+struct ClockPoint {
+    pos: usize,
+    time: Instant,
+    latency: Duration,
+}
+
+/// Latest audio callback's clock point, which the callback sets without locking
+struct AudioClock {
+    // Times are stored as nanoseconds from this
+    epoch: Instant,
+    // Incremented when the playback position is set, points from before that are stale
+    resets: AtomicU64,
+    // Resets, position, time and latency of the latest point
+    point: SeqLock<4>,
+}
+
+impl Default for AudioClock {
+    fn default() -> Self {
+        Self {
+            epoch: Instant::now(),
+            resets: AtomicU64::new(0),
+            point: SeqLock::new([u64::MAX, 0, 0, 0]),
+        }
+    }
+}
+
+impl AudioClock {
+    /// Forget the latest point, call after setting the playback position
+    fn reset(&self) {
+        self.resets.fetch_add(1, Ordering::Release);
+    }
+
+    /// Resets so far, load before the playback position to set a point with
+    fn resets(&self) -> u64 {
+        self.resets.load(Ordering::Acquire)
+    }
+
+    /// Set the latest point, only from the audio callback
+    fn set(&self, resets: u64, point: ClockPoint) {
+        let nanos = |duration: Duration| u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.point.write([
+            resets,
+            point.pos as u64,
+            nanos(point.time.saturating_duration_since(self.epoch)),
+            nanos(point.latency),
+        ]);
+    }
+
+    fn get(&self) -> Option<ClockPoint> {
+        let [resets, pos, time, latency] = self.point.read();
+        (resets == self.resets()).then(|| ClockPoint {
+            pos: pos as usize,
+            time: self.epoch + Duration::from_nanos(time),
+            latency: Duration::from_nanos(latency),
+        })
+    }
+}
+
+/// State shared with audio callbacks, which only use atomics so that they never wait
+#[derive(Clone, Default)]
+struct SharedParams {
+    stream: Option<Arc<stream::Stream>>,
+    playback_position: Arc<AtomicUsize>,
+    // Latest audio callback, reset when playback_position is changed
+    clock: Arc<AudioClock>,
+    gain: Arc<gain::GainControl>,
+    stats: Arc<stats::AudioCounters>,
+    playing: Arc<AtomicBool>,
+    error_sync_flag: Arc<AtomicBool>,
+}
+
+/// Where the player's audio goes and what drives its clock
+#[derive(Clone)]
+pub enum AudioBackend {
+    /// Audio output device, falls back to `Null` if the default device is not available.
+    /// Host is chosen by name and device by index or name, like `--list-audio-devices` shows them.
+    Device {
+        host: Option<String>,
+        device: Option<String>,
+    },
+    /// No audio output, time advances in real time
+    Null,
+    /// No audio output, time advances by a fixed step on every frame
+    FixedStep(Duration),
+}
+
+struct StartParams {
+    device: cpal::Device,
+    config: cpal::StreamConfig,
+    shared: SharedParams,
+}
+
+pub struct Player {
+    shared: SharedParams,
+    sample_rate: u32,
+    channels: u8,
+    // Playback position is counted in output samples, which may differ from the track's format
+    output_rate: u32,
+    output_channels: u8,
+    output_rate_channels: f32,
+    len_secs: f32,
+    window: Option<stream::Window>,
+    // Live input which is analyzed instead of the track
+    capture: Option<capture::Capture>,
+    beat_grid: Option<beat::BeatGrid>,
+    // Row start times when playing a tracker module
+    timeline: Option<Arc<tracker::Timeline>>,
+    analysis_table: Option<AnalysisTable>,
+    // Loaded file, which identifies the saved analysis
+    #[cfg(debug_assertions)]
+    source: Option<Arc<[u8]>>,
+    // Smoothed RMS level, follows the analysis table
+    loudness_envelope: Option<loudness::Envelope>,
+    playback_stream: Option<cpal::Stream>,
+    fixed_step: Option<Duration>,
+    // Playback speed multiplier, output positions advance at this rate relative to track time
+    rate: f32,
+    // Time stretch instead of resampling at other rates, so that pitch doesn't change
+    preserve_pitch: bool,
+    // Audio clock minus timer at the last correction
+    drift: Option<f32>,
+    // When the timer was last corrected, cleared when the timer is set
+    corrected_at: Option<Instant>,
+    // Seconds from audio callback to hearing the output, measured or set by user
+    measured_latency: f32,
+    latency: Option<f32>,
+    // Start and end seconds of the A/B loop
+    loop_region: Option<(f32, f32)>,
+    // Start and length seconds of volume fades
+    fade_in: Option<(f32, f32)>,
+    fade_out: Option<(f32, f32)>,
+    start_time: Instant,
+    pause_time: Instant,
+    time_offset: Duration,
+    analyzer: spectrum::Analyzer,
+}
+
+impl Player {
+    fn load(path: impl AsRef<Path>) -> Result<Arc<[u8]>> {
+        #[cfg(debug_assertions)]
+        {
+            Ok(std::fs::read(std::path::PathBuf::from(crate::RESOURCES_PATH).join(path))?.into())
+        }
+        #[cfg(not(debug_assertions))]
+        {
+            Ok(crate::RESOURCES_DIR
+                .get_file(path)
+                .expect("File not present in binary. This is a bug.")
+                .contents()
+                .into())
+        }
+    }
+
+    fn conf_meets_specs(
+        conf: &cpal::SupportedStreamConfigRange,
+        sample_rate: u32,
+        channels: u8,
+    ) -> bool {
+        conf.channels() == channels.into()
+            && conf.min_sample_rate() <= cpal::SampleRate(sample_rate)
+            && conf.max_sample_rate() >= cpal::SampleRate(sample_rate)
+    }
+
+    /// Audio host by name, or the default host
+    fn host(name: Option<&str>) -> Result<cpal::Host> {
+        match name {
+            Some(name) => {
+                let id = cpal::available_hosts()
+                    .into_iter()
+                    .find(|id| id.name().eq_ignore_ascii_case(name))
+                    .with_context(|| {
+                        format!(
+                            "Audio host {} is not available, see --list-audio-devices",
+                            name
+                        )
+                    })?;
+                cpal::host_from_id(id)
+                    .with_context(|| format!("Failed to initialize audio host {}", name))
+            }
+            None => Ok(cpal::default_host()),
+        }
+    }
+
+    /// Find a device by index or name
+    fn find_device(
+        mut devices: impl Iterator<Item = cpal::Device>,
+        selector: &str,
+    ) -> Option<cpal::Device> {
+        match selector.parse::<usize>() {
+            Ok(index) => devices.nth(index),
+            Err(_) => devices.find(|device| device.name().ok().as_deref() == Some(selector)),
+        }
+    }
+
+    fn init(
+        host: Option<&str>,
+        device: Option<&str>,
+        sample_rate: u32,
+        channels: u8,
+    ) -> Result<(cpal::Device, cpal::StreamConfig, cpal::SampleFormat)> {
+        let host = Self::host(host)?;
+        let device = match device {
+            Some(selector) => Self::find_device(
+                host.output_devices()
+                    .context("Failed to enumerate audio output devices")?,
+                selector,
+            )
+            .with_context(|| {
+                format!(
+                    "Audio output device {} not found on host {}, see --list-audio-devices",
+                    selector,
+                    host.id().name()
+                )
+            })?,
+            None => host
+                .default_output_device()
+                .context("Unable to find default audio output device")?,
+        };
+        let device_name = device.name().unwrap_or_else(|_| "(unknown)".into());
+        log::info!("Using audio output device {}", device_name);
+
+        // Find best configuration from device's supported configs. Prefer configs which can play
+        // the track as is, then ones with the same channel count, then stereo, and i16 samples.
+        let supported_config = device
+            .supported_output_configs()
+            .with_context(|| format!("Failed to query parameters of audio device {}", device_name))?
+            .max_by_key(|conf| {
+                (
+                    Self::conf_meets_specs(conf, sample_rate, channels),
+                    conf.channels() == channels.into(),
+                    conf.channels() == 2,
+                    conf.sample_format() == cpal::SampleFormat::I16,
+                )
+            })
+            .with_context(|| {
+                format!(
+                    "Audio device {} does not support any output configuration, can't play {}Hz {} channel audio",
+                    device_name, sample_rate, channels
+                )
+            })?;
+        if supported_config.sample_format() != cpal::SampleFormat::I16 {
+            log::warn!(
+                "Audio output device {} does not support i16 sample format",
+                device_name
+            );
+        }
+        let output_rate = sample_rate.clamp(
+            supported_config.min_sample_rate().0,
+            supported_config.max_sample_rate().0,
+        );
+        let supported_config = supported_config.with_sample_rate(cpal::SampleRate(output_rate));
+        if supported_config.sample_rate().0 != sample_rate
+            || supported_config.channels() != channels.into()
+        {
+            log::warn!(
+                "Audio device does not support {}Hz {} channel output, converting to {}Hz {} channels",
+                sample_rate,
+                channels,
+                supported_config.sample_rate().0,
+                supported_config.channels()
+            );
+        }
+
+        let format = supported_config.sample_format();
+        let buffer_size = supported_config.buffer_size().clone();
+        let mut config: cpal::StreamConfig = supported_config.into();
+
+        // Try to set small buffer size for minimal latency
+        match buffer_size {
+            cpal::SupportedBufferSize::Range { min, max } if min <= BUF_SIZE && max >= BUF_SIZE => {
+                config.buffer_size = cpal::BufferSize::Fixed(BUF_SIZE);
+                log::info!("Using audio output buffer size {}", BUF_SIZE);
+            }
+            _ => {
+                log::warn!(
+                    "Unable to set audio output buffer size, use --audio-latency-ms if demo runs out of sync"
+                );
+            }
+        }
+
+        Ok((device, config, format))
+    }
+
+    fn start<T: cpal::Sample>(p: StartParams) -> Result<cpal::Stream> {
+        let channels = usize::from(p.config.channels);
+        let smoothing = gain::smoothing(p.config.sample_rate.0);
+        let rate_channels = f64::from(p.config.sample_rate.0) * channels as f64;
+        let error_stats = p.shared.stats.clone();
+        let mut gain = gain::Gain::default();
+        // Time and duration of the previous callback's buffer
+        let mut last_buffer: Option<(Instant, Duration)> = None;
+        let mut last_resets = None;
+        let stream = p
+            .device
+            .build_output_stream(
+                &p.config,
+                move |data: &mut [T], info: &cpal::OutputCallbackInfo| {
+                    let started = Instant::now();
+                    let avail = data.len();
+                    let timestamp = info.timestamp();
+                    let latency = timestamp
+                        .playback
+                        .duration_since(&timestamp.callback)
+                        .unwrap_or_default();
+
+                    // Load position and advance to next audio slice
+                    // Might overflow in theory but not in realistic use
+                    // Clock is reset when playback starts, so the gap since last callback is expected
+                    let resets = p.shared.clock.resets();
+                    let resumed = last_resets != Some(resets);
+                    last_resets = Some(resets);
+                    let pos = p
+                        .shared
+                        .playback_position
+                        .fetch_add(avail, Ordering::Relaxed);
+                    p.shared.clock.set(
+                        resets,
+                        ClockPoint {
+                            pos,
+                            time: Instant::now(),
+                            latency,
+                        },
+                    );
+
+                    // How many i16s were available from the decoder thread
+                    let (written, underrun) = match &p.shared.stream {
+                        Some(stream) if p.shared.playing.load(Ordering::Relaxed) => {
+                            let written = stream.read(pos, data);
+                            (written, written < avail && !stream.is_end())
+                        }
+                        _ => (0, false),
+                    };
+
+                    // Output silence after end to avoid underruns
+                    for sample in data[written..].iter_mut() {
+                        *sample = cpal::Sample::from(&0.);
+                    }
+
+                    gain.apply(&p.shared.gain, pos, channels, smoothing, data);
+
+                    // Callback is late if the previous buffer ran out before it, with some slack
+                    let late = !resumed
+                        && matches!(last_buffer, Some((time, length))
+                            if started.duration_since(time) > length.mul_f32(1.5));
+                    last_buffer = Some((
+                        started,
+                        Duration::from_secs_f64(avail as f64 / rate_channels),
+                    ));
+                    p.shared
+                        .stats
+                        .add_callback(started.elapsed(), underrun, late);
+                },
+                move |err| {
+                    p.shared.error_sync_flag.store(true, Ordering::Relaxed);
+                    error_stats.add_error();
+                    log::error!("{}", err);
+                },
+            )
+            .context("Failed to build audio output stream")?;
+
+        Ok(stream)
+    }
+
+    /// Open audio output, and start decoding to the device's sample rate and channel count
+    ///
+    /// # Return value
+    ///
+    /// Returns the paused output stream, its sample rate and channel count
+    fn open(
+        decoder: Option<Box<dyn Decoder>>,
+        host: Option<&str>,
+        device: Option<&str>,
+        sample_rate: u32,
+        channels: u8,
+        shared: &mut SharedParams,
+    ) -> Result<(cpal::Stream, u32, u8)> {
+        // Initialize audio device
+        let (device, config, format) = Self::init(host, device, sample_rate, channels)?;
+        let output_channels =
+            u8::try_from(config.channels).context("Audio device has too many channels")?;
+        let output_rate = config.sample_rate.0;
+
+        // Start decoding
+        shared.stream = decoder.map(|decoder| {
+            stream::Stream::spawn(
+                decoder,
+                Converter::new(sample_rate, channels, output_rate, output_channels),
+                STREAM_BUF_SIZE * usize::from(output_channels),
+            )
+        });
+
+        // Start audio output stream
+        let start_parm = StartParams {
+            device,
+            config,
+            shared: shared.clone(),
+        };
+        let playback_stream = match format {
+            cpal::SampleFormat::I16 => Self::start::<i16>(start_parm)?,
+            cpal::SampleFormat::U16 => Self::start::<u16>(start_parm)?,
+            cpal::SampleFormat::F32 => Self::start::<f32>(start_parm)?,
+        };
+
+        // Start paused
+        playback_stream
+            .pause()
+            .unwrap_or_else(|e| log::error!("Cannot pause audio output stream: {}", e));
+
+        Ok((playback_stream, output_rate, output_channels))
+    }
+
+    pub fn new(path: impl AsRef<Path>, backend: AudioBackend) -> Result<Self> {
+        let path = path.as_ref();
+        log::info!("Loading {}", path.display());
+
+        let data = {
+            #[cfg(debug_assertions)]
+            {
+                match Self::load(path) {
+                    Ok(data) => Some(data),
+                    Err(e) => {
+                        log::warn!("Cannot load audio: {}", e);
+                        None
+                    }
+                }
+            }
+            #[cfg(not(debug_assertions))]
+            {
+                Some(Self::load(path)?)
+            }
+        };
+
+        // Prepare audio for seeking, and find analysis results from a previous start
+        let decoder = data
+            .clone()
+            .map(|data| decoder::open(path, data))
+            .transpose()?;
+        let cache = data.as_deref().and_then(analysis::Cache::load);
+        let player = Self::with_decoder(decoder, backend, cache)?;
+        #[cfg(debug_assertions)]
+        let player = {
+            let mut player = player;
+            player.source = data;
+            player
+        };
+
+        Ok(player)
+    }
+
+    /// Save analysis results to resources, for the next start and for embedding into release
+    /// builds
+    #[cfg(debug_assertions)]
+    pub fn save_analysis(&self) -> Result<()> {
+        match (&self.source, &self.beat_grid, &self.analysis_table) {
+            (Some(source), Some(beat_grid), Some(table)) => {
+                analysis::Cache::new(source, beat_grid.clone(), table.clone()).save()
+            }
+            _ => Err(anyhow!("No audio analysis to save")),
+        }
+    }
+
+    /// Play a looping click track instead of music, for calibrating audio latency
+    pub fn click_track(backend: AudioBackend) -> Result<Self> {
+        log::info!("Generating click track");
+        let mut player =
+            Self::with_decoder(Some(Box::new(click::ClickTrack::new())), backend, None)?;
+        player.set_loop(Some((0., player.len_secs)));
+        Ok(player)
+    }
+
+    /// Analyze audio from an input device instead of playing music. Time runs freely from 0 on.
+    pub fn live_input(host: Option<&str>, device: Option<&str>) -> Result<Self> {
+        let host = Self::host(host)?;
+        let device = match device {
+            Some(selector) => Self::find_device(
+                host.input_devices()
+                    .context("Failed to enumerate audio input devices")?,
+                selector,
+            )
+            .with_context(|| {
+                format!(
+                    "Audio input device {} not found on host {}, see --list-audio-devices",
+                    selector,
+                    host.id().name()
+                )
+            })?,
+            None => host
+                .default_input_device()
+                .context("Unable to find default audio input device")?,
+        };
+        let capture = capture::Capture::start(&device, CAPTURE_BUF_SIZE)?;
+
+        let mut player = Self::with_decoder(None, AudioBackend::Null, None)?;
+        player.sample_rate = capture.sample_rate();
+        player.channels = capture.channels();
+        player.len_secs = f32::INFINITY;
+        player.capture = Some(capture);
+        Ok(player)
+    }
+
+    fn with_decoder(
+        decoder: Option<Box<dyn Decoder>>,
+        backend: AudioBackend,
+        cache: Option<analysis::Cache>,
+    ) -> Result<Self> {
+        let (sample_rate, channels, len_frames) = decoder
+            .as_ref()
+            .map(|decoder| {
+                (
+                    decoder.sample_rate(),
+                    decoder.channels(),
+                    decoder.len_frames(),
+                )
+            })
+            .unwrap_or((48000, 2, 0));
+        let len_secs = len_frames as f32 / sample_rate as f32;
+        let timeline = decoder.as_ref().and_then(|decoder| decoder.timeline());
+
+        // Second decoder for random access by audio analysis
+        let window = decoder
+            .as_ref()
+            .map(|decoder| decoder.try_clone().map(stream::Window::new))
+            .transpose()?;
+
+        // Offline analysis, decoding the track once for both the beat grid and the table
+        let (beat_grid, analysis_table, decoded) = match cache {
+            Some(cache) => {
+                log::info!("Using cached audio analysis");
+                (Some(cache.beat_grid), Some(cache.table), None)
+            }
+            None => match &decoder {
+                Some(decoder) => {
+                    log::info!("Analyzing beats");
+                    let decoded = decoder::Decoded::new(decoder.try_clone()?)?;
+                    let beat_grid = beat::BeatGrid::analyze(decoded.try_clone()?)?;
+                    (Some(beat_grid), None, Some(decoded))
+                }
+                None => (None, None, None),
+            },
+        };
+
+        let mut shared = SharedParams::default();
+        let (playback_stream, output_rate, output_channels) = match &backend {
+            AudioBackend::Device { host, device } => match Self::open(
+                decoder,
+                host.as_deref(),
+                device.as_deref(),
+                sample_rate,
+                channels,
+                &mut shared,
+            ) {
+                Ok((playback_stream, output_rate, output_channels)) => {
+                    (Some(playback_stream), output_rate, output_channels)
+                }
+                // Explicitly chosen output is expected to work
+                Err(e) if host.is_some() || device.is_some() => {
+                    if let Some(stream) = shared.stream.take() {
+                        stream.quit();
+                    }
+                    return Err(e);
+                }
+                Err(e) => {
+                    log::warn!("{:?}", e);
+                    log::warn!("Continuing without audio output");
+                    if let Some(stream) = shared.stream.take() {
+                        stream.quit();
+                    }
+                    (None, sample_rate, channels)
+                }
+            },
+            AudioBackend::Null | AudioBackend::FixedStep(_) => (None, sample_rate, channels),
+        };
+        let fixed_step = match &backend {
+            AudioBackend::FixedStep(step) => Some(*step),
+            _ => None,
+        };
+
+        let time = Instant::now();
+
+        let mut player = Self {
+            shared,
+            sample_rate,
+            channels,
+            output_rate,
+            output_channels,
+            output_rate_channels: (output_rate * u32::from(output_channels)) as f32,
+            len_secs,
+            window,
+            capture: None,
+            beat_grid,
+            timeline,
+            analysis_table,
+            #[cfg(debug_assertions)]
+            source: None,
+            loudness_envelope: None,
+            playback_stream,
+            fixed_step,
+            rate: 1.,
+            preserve_pitch: false,
+            drift: None,
+            corrected_at: None,
+            measured_latency: 0.,
+            latency: None,
+            loop_region: None,
+            fade_in: None,
+            fade_out: None,
+            start_time: time,
+            pause_time: time,
+            time_offset: Duration::new(0, 0),
+            analyzer: spectrum::Analyzer::new(FFT_SIZE, WindowFunction::Hann),
+        };
+
+        // Precompute spectrum analysis so that there's no FFT to do while rendering
+        if let Some(decoded) = decoded {
+            log::info!("Analyzing audio");
+            player.analysis_table = Some(player.compute_analysis(Box::new(decoded)));
+        }
+        player.set_loudness_envelope(loudness::ATTACK_SECS, loudness::RELEASE_SECS);
+
+        Ok(player)
+    }
+
+    pub fn len_secs(&self) -> f32 {
+        self.len_secs
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.shared.playing.load(Ordering::Relaxed)
+    }
+
+    pub fn play(&mut self) {
+        self.shared.clock.reset();
+        let pos = self.shared.playback_position.load(Ordering::Relaxed);
+        self.time_offset = self.pos_to_duration(pos);
+        self.start_time = Instant::now();
+        self.corrected_at = None;
+        self.shared.playing.store(true, Ordering::Relaxed);
+        if let Some(playback_stream) = &self.playback_stream {
+            playback_stream
+                .play()
+                .unwrap_or_else(|e| log::error!("Cannot play audio output stream: {}", e));
+        }
+    }
+
+    pub fn pause(&mut self) {
+        // Without audio output there's no callback advancing the playback position
+        if self.playback_stream.is_none() {
+            let secs = self.time_secs();
+            let pos = self.secs_to_pos(secs);
+            self.shared.playback_position.store(pos, Ordering::Relaxed);
+        }
+
+        self.pause_time = Instant::now();
+        self.drift = None;
+        self.shared.playing.store(false, Ordering::Relaxed);
+        if let Some(playback_stream) = &self.playback_stream {
+            playback_stream
+                .pause()
+                .unwrap_or_else(|e| log::error!("Cannot pause audio output stream: {}", e));
+        }
+    }
+
+    /// Call once per frame, advances the clock of the fixed step backend
+    pub fn advance_frame(&mut self) {
+        if let Some(step) = self.fixed_step {
+            if self.is_playing() {
+                self.time_offset += step.mul_f32(self.rate);
+            }
+        }
+    }
+
+    pub fn time_secs(&mut self) -> f32 {
+        let timer_secs = (if self.fixed_step.is_some() {
+            Duration::ZERO
+        } else if self.is_playing() {
+            if self
+                .shared
+                .error_sync_flag
+                .fetch_and(false, Ordering::Relaxed)
+            {
+                log::info!("Audio output errors, trying to sync");
+                self.play();
+            }
+            self.sync_to_audio_clock();
+            self.start_time.elapsed().mul_f32(self.rate)
+        } else {
+            self.pause_time
+                .duration_since(self.start_time)
+                .mul_f32(self.rate)
+        } + self.time_offset)
+            .as_secs_f32();
+        let timer_secs = stream::wrap(timer_secs, self.loop_region);
+
+        // Hack to enable development without audio track
+        #[cfg(debug_assertions)]
+        if self.window.is_none() {
+            return timer_secs;
+        }
+
+        timer_secs.min(self.len_secs)
+    }
+
+    /// Correct the timer towards the position of the audio being heard
+    fn sync_to_audio_clock(&mut self) {
+        let point = match self.shared.clock.get() {
+            Some(point) => point,
+            None => return,
+        };
+
+        self.measured_latency = point.latency.as_secs_f32();
+        let latency = self.latency.unwrap_or(self.measured_latency);
+
+        let now = Instant::now();
+        let heard_secs = self.pos_to_duration(point.pos).as_secs_f32()
+            + (now.duration_since(point.time).as_secs_f32() - latency) * self.rate;
+        let timer_secs = (now.duration_since(self.start_time).mul_f32(self.rate)
+            + self.time_offset)
+            .as_secs_f32();
+        let drift = heard_secs - timer_secs;
+        self.drift = Some(drift);
+        let elapsed = self.corrected_at.map_or(0., |time| {
+            now.duration_since(time).as_secs_f32() * self.rate
+        });
+        self.corrected_at = Some(now);
+
+        // Jump ahead over large errors, eg. after device hiccups, and smooth out small ones.
+        // Only seeks move time backwards.
+        let correction = if drift > MAX_DRIFT {
+            drift
+        } else {
+            (drift * DRIFT_SLEW).max(-elapsed * MAX_SLOWDOWN)
+        };
+        self.time_offset = if correction >= 0. {
+            self.time_offset + Duration::from_secs_f32(correction)
+        } else {
+            self.time_offset
+                .saturating_sub(Duration::from_secs_f32(-correction))
+        };
+    }
+
+    /// Audio output latency in seconds which is compensated for
+    pub fn latency_secs(&self) -> f32 {
+        self.latency.unwrap_or(self.measured_latency)
+    }
+
+    /// Override the latency reported by the audio device, eg. to account for video latency
+    pub fn set_latency(&mut self, secs: Option<f32>) {
+        self.latency = secs;
+        log::info!("Audio latency {:.0}ms", self.latency_secs() * 1000.);
+    }
+
+    /// Audio output counters since start, all zero without audio output
+    pub fn audio_stats(&self) -> AudioStats {
+        self.shared.stats.get()
+    }
+
+    /// Difference between audio and timer seconds at the last time query, when audio is playing
+    pub fn drift_secs(&self) -> Option<f32> {
+        self.drift
+    }
+
+    pub fn seek(&mut self, secs: f32) {
+        // Calculate new playback position
+        let pos = self.secs_to_pos(secs);
+
+        // Set new position and update timing etc
+        if let Some(stream) = &self.shared.stream {
+            stream.set_loop(self.loop_positions());
+            stream.seek(pos, f64::from(self.rate), self.preserve_pitch);
+        }
+        self.update_envelope();
+        self.shared.playback_position.store(pos, Ordering::Relaxed);
+        self.shared.clock.reset();
+        self.time_offset = self.pos_to_duration(pos);
+        let time = Instant::now();
+        self.start_time = time;
+        self.pause_time = time;
+        self.corrected_at = None;
+    }
+
+    pub fn playback_rate(&self) -> f32 {
+        self.rate
+    }
+
+    /// Change playback speed, eg. 0.5 for half speed.
+    /// Pitch changes along with the speed unless it's preserved with [`Player::set_preserve_pitch`].
+    pub fn set_playback_rate(&mut self, rate: f32) {
+        let rate = rate.clamp(MIN_RATE, MAX_RATE);
+        if rate == self.rate {
+            return;
+        }
+
+        // Output positions map to track time differently at the new rate, restart from here
+        let secs = self.time_secs();
+        self.rate = rate;
+        self.seek(secs);
+        log::info!("Playback rate {}x", rate);
+    }
+
+    pub fn preserves_pitch(&self) -> bool {
+        self.preserve_pitch
+    }
+
+    /// Keep the original pitch at other playback rates, at the cost of some smearing
+    pub fn set_preserve_pitch(&mut self, preserve_pitch: bool) {
+        if preserve_pitch == self.preserve_pitch {
+            return;
+        }
+        self.preserve_pitch = preserve_pitch;
+        let secs = self.time_secs();
+        self.seek(secs);
+        log::info!(
+            "Pitch {} at other playback rates",
+            if preserve_pitch {
+                "preserved"
+            } else {
+                "follows speed"
+            }
+        );
+    }
+
+    pub fn loop_region(&self) -> Option<(f32, f32)> {
+        self.loop_region
+    }
+
+    /// Repeat playback between start and end seconds, or play normally with `None`
+    pub fn set_loop(&mut self, region: Option<(f32, f32)>) {
+        let secs = self.time_secs();
+        self.loop_region = match region {
+            Some((start, end)) => {
+                // Without audio track there's no length to limit to
+                let end = if self.len_secs > 0. {
+                    end.min(self.len_secs)
+                } else {
+                    end
+                };
+                if start.max(0.) < end {
+                    log::info!("Looping from {:.3}s to {:.3}s", start.max(0.), end);
+                    Some((start.max(0.), end))
+                } else {
+                    log::warn!("Invalid loop region {}s to {}s", start, end);
+                    self.loop_region
+                }
+            }
+            None => {
+                log::info!("Loop cleared");
+                None
+            }
+        };
+
+        // Restart decoding so that the loop is applied to audio already buffered
+        self.seek(secs);
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.shared.gain.volume()
+    }
+
+    /// Set master volume, 1 for the track's original level
+    pub fn set_volume(&mut self, volume: f32) {
+        self.shared.gain.set_volume(volume);
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.shared.gain.is_muted()
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.shared.gain.set_muted(muted);
+    }
+
+    /// Fade in from silence and fade out to silence, given as start and length seconds
+    pub fn set_fades(&mut self, fade_in: Option<(f32, f32)>, fade_out: Option<(f32, f32)>) {
+        self.fade_in = fade_in;
+        self.fade_out = fade_out;
+        self.update_envelope();
+    }
+
+    /// Convert fades to output positions, which depend on playback rate
+    fn update_envelope(&self) {
+        let to_pos = |(start, length): (f32, f32)| {
+            (self.secs_to_pos(start), self.secs_to_pos(start + length))
+        };
+        self.shared.gain.set_envelope(
+            self.fade_in.map(to_pos),
+            self.fade_out.map(to_pos),
+            self.loop_positions(),
+        );
+    }
+
+    /// Loop region in output positions
+    fn loop_positions(&self) -> Option<(usize, usize)> {
+        self.loop_region
+            .map(|(start, end)| (self.secs_to_pos(start), self.secs_to_pos(end)))
+    }
+
+    fn secs_to_pos(&self, secs: f32) -> usize {
+        let pos = (secs / self.rate * self.output_rate_channels) as usize;
+
+        // Align to channel
+        pos - pos % usize::from(self.output_channels)
+    }
+
+    fn pos_to_duration(&self, pos: usize) -> Duration {
+        let sample_rate_channels = u64::from(self.output_rate) * u64::from(self.output_channels);
+        let pos = u64::try_from(pos).unwrap();
+        Duration::new(
+            pos / sample_rate_channels,
+            u32::try_from(((pos % sample_rate_channels) * 1_000_000_000) / sample_rate_channels)
+                .unwrap(),
+        )
+        .mul_f32(self.rate)
+    }
+
+    /// Set FFT size in frames and window function for spectrum analysis. The size must be a
+    /// power of two of at least 2.
+    pub fn set_spectrum_config(
+        &mut self,
+        fft_size: usize,
+        window_function: WindowFunction,
+    ) -> Result<()> {
+        if fft_size < 2 || !fft_size.is_power_of_two() {
+            return Err(anyhow!(
+                "FFT size must be a power of two of at least 2, not {}",
+                fft_size
+            ));
+        }
+        self.analyzer = spectrum::Analyzer::new(fft_size, window_function);
+        Ok(())
+    }
+
+    /// Frame position for analyzing `frames` frames, limited to audio data range
+    fn analysis_pos(&self, at_secs: f32, frames: usize) -> Option<u64> {
+        self.window.as_ref()?;
+
+        // Compute the position
+        let len_frames = (self.len_secs * self.sample_rate as f32) as usize;
+        let pos = (at_secs * self.sample_rate as f32) as usize;
+
+        if pos >= len_frames {
+            return None;
+        }
+
+        // Limit to audio data range
+        Some(pos.min(len_frames.saturating_sub(frames + 1)) as u64)
+    }
+
+    /// Samples for analyzing `frames` frames, the latest ones when capturing live input
+    fn analysis_samples(&mut self, at_secs: f32, frames: usize) -> Option<&[i16]> {
+        if self.capture.is_some() {
+            return Some(self.capture.as_mut()?.latest(frames));
+        }
+        let pos = self.analysis_pos(at_secs, frames)?;
+        Some(self.window.as_mut()?.get(pos, frames))
+    }
+
+    fn analyze(&mut self, at_secs: f32, mode: ChannelMode) -> Option<&spectrum::Analyzer> {
+        let channels = usize::from(self.channels);
+        let fft_size = self.analyzer.size();
+        let pos = match &self.capture {
+            Some(capture) => capture.position(),
+            None => self.analysis_pos(at_secs, fft_size)?,
+        };
+
+        // Reuse the previous transform when analyzing the same position again
+        if self.analyzer.position() != Some((pos, mode)) {
+            let samples = match &mut self.capture {
+                Some(capture) => capture.latest(fft_size),
+                None => self.window.as_mut()?.get(pos, fft_size),
+            };
+            if samples.len() < fft_size * channels {
+                return None;
+            }
+            self.analyzer.process(pos, samples, channels, mode);
+        }
+
+        Some(&self.analyzer)
+    }
+
+    /// Compute average magnitudes of frequency bands, see [`Band::ALL`] for the defaults
+    pub fn bands(&mut self, at_secs: f32, mode: ChannelMode, bands: &[Band]) -> Vec<f32> {
+        let sample_rate = self.sample_rate;
+        match self.analyze(at_secs, mode) {
+            Some(analyzer) => bands
+                .iter()
+                .map(|band| analyzer.band(sample_rate, *band))
+                .collect(),
+            None => vec![0.; bands.len()],
+        }
+    }
+
+    /// Compute a spectrum of `bins` logarithmically spaced bands over a frequency range
+    pub fn spectrum(
+        &mut self,
+        at_secs: f32,
+        mode: ChannelMode,
+        range: Band,
+        bins: usize,
+    ) -> Vec<f32> {
+        let sample_rate = self.sample_rate;
+        let mut spectrum = vec![0.; bins];
+        if let Some(analyzer) = self.analyze(at_secs, mode) {
+            analyzer.log_spectrum(sample_rate, range, &mut spectrum);
+        }
+        spectrum
+    }
+
+    /// Compute left and right RMS levels and stereo width over an FFT size window
+    pub fn stereo_levels(&mut self, at_secs: f32) -> StereoLevels {
+        let channels = usize::from(self.channels);
+        let frames = self.analyzer.size();
+        self.analysis_samples(at_secs, frames)
+            .map(|samples| StereoLevels::from_samples(samples, channels))
+            .unwrap_or_default()
+    }
+
+    /// Compute RMS and peak levels of all channels over an FFT size window
+    pub fn loudness(&mut self, at_secs: f32) -> Loudness {
+        let frames = self.analyzer.size();
+        self.analysis_samples(at_secs, frames)
+            .map(Loudness::from_samples)
+            .unwrap_or_default()
+    }
+
+    /// Set attack and release time constants of the loudness envelope in seconds
+    pub fn set_loudness_envelope(&mut self, attack_secs: f32, release_secs: f32) {
+        self.loudness_envelope = self.analysis_table.as_ref().map(|table| {
+            let levels: Vec<f32> = table.frames().iter().map(|frame| frame.rms).collect();
+            loudness::Envelope::follow(&levels, table.rate(), attack_secs, release_secs)
+        });
+    }
+
+    /// RMS level smoothed with the attack and release of [`Player::set_loudness_envelope`]
+    pub fn loudness_envelope(&self, at_secs: f32) -> f32 {
+        self.loudness_envelope
+            .as_ref()
+            .map(|envelope| envelope.get(at_secs))
+            .unwrap_or(0.)
+    }
+
+    /// Tempo detected by beat analysis
+    pub fn bpm(&self) -> Option<f32> {
+        self.beat_grid.as_ref().map(beat::BeatGrid::bpm)
+    }
+
+    /// Beat grid and onset values from offline analysis
+    pub fn beat_info(&self, at_secs: f32) -> BeatInfo {
+        self.beat_grid
+            .as_ref()
+            .map(|beat_grid| beat_grid.beat_info(at_secs))
+            .unwrap_or_default()
+    }
+
+    /// Order, pattern and row of a tracker module
+    pub fn tracker_position(&self, at_secs: f32) -> Option<TrackerPosition> {
+        self.timeline.as_ref()?.position(at_secs)
+    }
+
+    /// Rows of a tracker module played before `at_secs`, with the fraction of the current row
+    pub fn tracker_row(&self, at_secs: f32) -> Option<f32> {
+        Some(self.timeline.as_ref()?.row(at_secs))
+    }
+
+    /// Seconds when a row given by [`Player::tracker_row`] is played
+    pub fn tracker_row_secs(&self, row: f32) -> Option<f32> {
+        Some(self.timeline.as_ref()?.secs(row))
+    }
+
+    /// Get analysis results from the precomputed table, or compute them now if there's none
+    pub fn analysis(&mut self, at_secs: f32) -> AnalysisFrame {
+        match &self.analysis_table {
+            Some(table) => table.get(at_secs),
+            None => self.analyze_frame(at_secs),
+        }
+    }
+
+    pub fn analysis_table(&self) -> Option<&AnalysisTable> {
+        self.analysis_table.as_ref()
+    }
+
+    fn analyze_frame(&mut self, at_secs: f32) -> AnalysisFrame {
+        let levels = self.stereo_levels(at_secs);
+        let loudness = self.loudness(at_secs);
+        AnalysisFrame {
+            bass: self.bass_psd(at_secs),
+            bands: self
+                .bands(at_secs, ChannelMode::Mono, &Band::ALL)
+                .try_into()
+                .unwrap_or_default(),
+            rms: loudness.rms,
+            peak: loudness.peak,
+            onset: self.beat_info(at_secs).onset_strength,
+            levels,
+        }
+    }
+
+    /// Analyze the whole track from `decoded` instead of decoding it again through the window
+    fn compute_analysis(&mut self, decoded: Box<dyn Decoder>) -> AnalysisTable {
+        let window = self.window.replace(stream::Window::new(decoded));
+        let frames = (self.len_secs * AnalysisTable::RATE).ceil() as usize;
+        let table = AnalysisTable::new(
+            (0..frames)
+                .map(|i| self.analyze_frame(i as f32 / AnalysisTable::RATE))
+                .collect(),
+        );
+        self.window = window;
+        table
+    }
+
+    /// Compute average Power Spectral Density of bass (60-600Hz)
+    pub fn bass_psd(&mut self, at_secs: f32) -> f32 {
+        // The original analysis labeled this 30-300Hz but mapped bins to half of their real
+        // frequency, so it really was 60-600Hz. Kept because sync tracks are tuned for it.
+        let sample_rate = self.sample_rate;
+        self.analyze(at_secs, ChannelMode::First)
+            .map(|analyzer| analyzer.band(sample_rate, Band::new(60., 600.)))
+            .unwrap_or(0.)
+    }
+}
+
+impl Drop for Player {
+    fn drop(&mut self) {
+        if let Some(stream) = &self.shared.stream {
+            stream.quit();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_invalid_fft_size() {
+        let mut player = Player::with_decoder(None, AudioBackend::Null, None).unwrap();
+        for size in [0, 1, 1000] {
+            assert!(player
+                .set_spectrum_config(size, WindowFunction::Hann)
+                .is_err());
+        }
+        player
+            .set_spectrum_config(2048, WindowFunction::Hann)
+            .unwrap();
+        assert_eq!(
+            player.bands(0., ChannelMode::Mono, &Band::ALL).len(),
+            Band::ALL.len()
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use super::decoder::Decoder;
+use anyhow::{Context, Result};
+use lewton::inside_ogg::OggStreamReader;
+use std::{io::Cursor, sync::Arc};
+
+type Reader = OggStreamReader<Cursor<Arc<[u8]>>>;
+
+/// Seekable Ogg Vorbis decoder which keeps the compressed data in memory
+pub struct OggDecoder {
+    data: Arc<[u8]>,
+    reader: Reader,
+    // Granule positions (frames) at the end of each page, used as seek anchors
+    page_ends: Arc<Vec<u64>>,
+    sample_rate: u32,
+    channels: u8,
+    // Frame position of the next decoded packet, unknown right after a page seek
+    position: Option<u64>,
+    // Decoded frames before this position are discarded
+    target: u64,
+}
+
+impl OggDecoder {
+    fn reader(data: &Arc<[u8]>) -> Result<Reader> {
+        OggStreamReader::new(Cursor::new(data.clone())).context("Failed to read ogg headers")
+    }
+
+    pub fn new(data: Arc<[u8]>) -> Result<Self> {
+        // Build the seek index by scanning packets without decoding audio
+        let mut packets = Self::reader(&data)?.into_inner();
+        let mut page_ends = Vec::new();
+        while let Some(packet) = packets.read_packet().context("Failed to read ogg stream")? {
+            if packet.last_in_page() && page_ends.last() < Some(&packet.absgp_page()) {
+                page_ends.push(packet.absgp_page());
+            }
+        }
+
+        let reader = Self::reader(&data)?;
+        Ok(Self {
+            sample_rate: reader.ident_hdr.audio_sample_rate,
+            channels: reader.ident_hdr.audio_channels,
+            data,
+            reader,
+            page_ends: Arc::new(page_ends),
+            position: Some(0),
+            target: 0,
+        })
+    }
+}
+
+impl Decoder for OggDecoder {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    fn len_frames(&self) -> u64 {
+        self.page_ends.last().copied().unwrap_or(0)
+    }
+
+    fn seek(&mut self, frame: u64) -> Result<()> {
+        // Find the last page which ends before the target. Decoding the rest of that page
+        // primes the decoder and tells the exact position where the next page starts.
+        let pages_before = self.page_ends.partition_point(|&end| end <= frame);
+        if pages_before == 0 {
+            self.reader = Self::reader(&self.data)?;
+            self.position = Some(0);
+        } else {
+            self.reader
+                .seek_absgp_pg(self.page_ends[pages_before - 1])
+                .context("Failed to seek ogg stream")?;
+            self.position = None;
+        }
+        self.target = frame;
+        Ok(())
+    }
+
+    fn read(&mut self, out: &mut Vec<i16>) -> Result<bool> {
+        let channels = usize::from(self.channels);
+        loop {
+            let packet = match self
+                .reader
+                .read_dec_packet_itl()
+                .context("Failed to decode ogg stream")?
+            {
+                Some(packet) => packet,
+                None => return Ok(false),
+            };
+
+            let position = match self.position {
+                Some(position) => position,
+                None => {
+                    // Samples before the first page end after a seek are only for priming
+                    self.position = self.reader.get_last_absgp();
+                    continue;
+                }
+            };
+            let frames = (packet.len() / channels) as u64;
+            self.position = Some(position + frames);
+
+            let skip = self.target.saturating_sub(position).min(frames) as usize;
+            if skip < packet.len() / channels {
+                out.extend_from_slice(&packet[skip * channels..]);
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Create another decoder for the same stream, sharing the seek index
+    fn try_clone(&self) -> Result<Box<dyn Decoder>> {
+        Ok(Box::new(Self {
+            data: self.data.clone(),
+            reader: Self::reader(&self.data)?,
+            page_ends: self.page_ends.clone(),
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            position: Some(0),
+            target: 0,
+        }))
+    }
+}
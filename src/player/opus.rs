@@ -0,0 +1,180 @@
+use super::decoder::Decoder;
+use anyhow::{anyhow, Context, Result};
+use ogg::PacketReader;
+use std::{io::Cursor, sync::Arc};
+
+/// Opus always decodes at 48 kHz, granule positions count frames at that rate
+const SAMPLE_RATE: u32 = 48000;
+/// How much audio to decode before a seek target for the decoder to converge. RFC 7845 asks
+/// for at least 80 ms, 320 ms makes the output practically identical to decoding from the start.
+const PREROLL_FRAMES: u64 = 15360;
+/// Longest possible packet, 120 ms
+const MAX_PACKET_FRAMES: usize = 5760;
+
+/// Ogg Opus stream split into packets once, shared between clones
+struct Stream {
+    channels: u8,
+    // Frames to discard from the start of the decoded stream
+    pre_skip: u64,
+    // Frames after the pre-skip
+    len_frames: u64,
+    packets: Vec<Vec<u8>>,
+    // Stream frame at the start of each packet, including the pre-skip
+    packet_starts: Vec<u64>,
+}
+
+impl Stream {
+    fn new(data: Arc<[u8]>) -> Result<Self> {
+        let mut reader = PacketReader::new(Cursor::new(data));
+        let mut read_packet =
+            || -> Result<_> { reader.read_packet().context("Failed to read ogg stream") };
+
+        // OpusHead: magic, version, channel count, pre-skip, input sample rate, gain and
+        // channel mapping family
+        let head = read_packet()?.context("Opus stream has no headers")?.data;
+        if head.len() < 19 || !head.starts_with(b"OpusHead") {
+            return Err(anyhow!("Invalid OpusHead packet"));
+        }
+        let channels = head[9];
+        let pre_skip = u64::from(u16::from_le_bytes([head[10], head[11]]));
+        if head[18] != 0 || !(1..=2).contains(&channels) {
+            return Err(anyhow!(
+                "Only mono and stereo Opus are supported, got {} channels with mapping {}",
+                channels,
+                head[18]
+            ));
+        }
+        read_packet()?.context("Opus stream has no OpusTags packet")?;
+
+        let mut packets = Vec::new();
+        let mut packet_starts = Vec::new();
+        let mut position = 0;
+        let mut end = None;
+        while let Some(packet) = read_packet()? {
+            if packet.last_in_stream() {
+                end = Some(packet.absgp_page());
+            }
+            packet_starts.push(position);
+            position += packet_frames(&packet.data)?;
+            packets.push(packet.data);
+        }
+
+        // The last page's granule position trims the padding of the last packet
+        let end = end.unwrap_or(position).min(position);
+        Ok(Self {
+            channels,
+            pre_skip,
+            len_frames: end.saturating_sub(pre_skip),
+            packets,
+            packet_starts,
+        })
+    }
+}
+
+/// Frames in a packet at 48 kHz, from its TOC byte (RFC 6716 section 3.1)
+fn packet_frames(packet: &[u8]) -> Result<u64> {
+    let toc = *packet.first().context("Empty Opus packet")?;
+    let config = toc >> 3;
+    let frame_size = match config {
+        // SILK: 10, 20, 40 or 60 ms
+        0..=11 => [480, 960, 1920, 2880][usize::from(config % 4)],
+        // Hybrid: 10 or 20 ms
+        12..=15 => [480, 960][usize::from(config % 2)],
+        // CELT: 2.5, 5, 10 or 20 ms
+        _ => [120, 240, 480, 960][usize::from(config % 4)],
+    };
+    let frames = match toc & 3 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => packet.get(1).context("Truncated Opus packet")? & 0x3f,
+    };
+    Ok(frame_size * u64::from(frames))
+}
+
+/// Seekable Ogg Opus decoder which keeps the compressed packets in memory
+pub struct OpusDecoder {
+    stream: Arc<Stream>,
+    decoder: opus_decoder::OpusDecoder,
+    // Index of the next packet to decode
+    packet: usize,
+    // Decoded frames before this stream frame are discarded
+    target: u64,
+    buffer: Vec<i16>,
+}
+
+impl OpusDecoder {
+    pub fn new(data: Arc<[u8]>) -> Result<Self> {
+        Self::with_stream(Arc::new(Stream::new(data)?))
+    }
+
+    fn with_stream(stream: Arc<Stream>) -> Result<Self> {
+        let decoder = opus_decoder::OpusDecoder::new(SAMPLE_RATE, usize::from(stream.channels))
+            .context("Failed to create Opus decoder")?;
+        Ok(Self {
+            buffer: vec![0; MAX_PACKET_FRAMES * usize::from(stream.channels)],
+            target: stream.pre_skip,
+            stream,
+            decoder,
+            packet: 0,
+        })
+    }
+}
+
+impl Decoder for OpusDecoder {
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn channels(&self) -> u8 {
+        self.stream.channels
+    }
+
+    fn len_frames(&self) -> u64 {
+        self.stream.len_frames
+    }
+
+    fn seek(&mut self, frame: u64) -> Result<()> {
+        // Start decoding from the packet containing the preroll start and discard the
+        // output until the target
+        self.target = self.stream.pre_skip + frame;
+        let preroll = self.target.saturating_sub(PREROLL_FRAMES);
+        self.packet = self
+            .stream
+            .packet_starts
+            .partition_point(|&start| start <= preroll)
+            .saturating_sub(1);
+        self.decoder.reset();
+        Ok(())
+    }
+
+    fn read(&mut self, out: &mut Vec<i16>) -> Result<bool> {
+        let channels = usize::from(self.stream.channels);
+        let end = self.stream.pre_skip + self.stream.len_frames;
+        while let Some(packet) = self.stream.packets.get(self.packet) {
+            let position = self.stream.packet_starts[self.packet];
+            self.packet += 1;
+            if position >= end {
+                break;
+            }
+
+            let frames = self
+                .decoder
+                .decode(packet, &mut self.buffer, false)
+                .context("Failed to decode Opus stream")? as u64;
+            let skip = self.target.saturating_sub(position).min(frames);
+            let take = (position + frames).min(end).saturating_sub(position);
+            if skip < take {
+                out.extend_from_slice(
+                    &self.buffer[skip as usize * channels..take as usize * channels],
+                );
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Create another decoder for the same stream, sharing the packets
+    fn try_clone(&self) -> Result<Box<dyn Decoder>> {
+        Ok(Box::new(Self::with_stream(self.stream.clone())?))
+    }
+}
@@ -0,0 +1,98 @@
+use std::sync::atomic::{AtomicI16, AtomicUsize, Ordering};
+
+/// Lock-free single producer, single consumer queue of samples, so that an audio callback
+/// never waits for the thread on the other side
+pub struct Ring {
+    samples: Box<[AtomicI16]>,
+    // Samples pushed and popped since creation, indices to samples wrap around
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+impl Ring {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            samples: (0..capacity.max(1)).map(|_| AtomicI16::new(0)).collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Index of the next sample to be pushed
+    pub fn head(&self) -> usize {
+        self.head.load(Ordering::Acquire)
+    }
+
+    /// Index of the next sample to be popped
+    pub fn tail(&self) -> usize {
+        self.tail.load(Ordering::Acquire)
+    }
+
+    /// Samples which can be popped
+    pub fn len(&self) -> usize {
+        self.head().wrapping_sub(self.tail())
+    }
+
+    /// Samples which can be pushed
+    pub fn free(&self) -> usize {
+        self.samples.len() - self.len()
+    }
+
+    /// Push as many of `samples` as fit, called only by the producer
+    ///
+    /// # Return value
+    ///
+    /// Returns how many samples were pushed
+    pub fn push(&self, samples: &[i16]) -> usize {
+        let head = self.head.load(Ordering::Relaxed);
+        let count = samples.len().min(self.free());
+        for (i, &sample) in samples[..count].iter().enumerate() {
+            self.samples[head.wrapping_add(i) % self.samples.len()]
+                .store(sample, Ordering::Relaxed);
+        }
+        self.head.store(head.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    /// Pop up to `max` samples to `f`, called only by the consumer
+    ///
+    /// # Return value
+    ///
+    /// Returns how many samples were popped
+    pub fn pop(&self, max: usize, mut f: impl FnMut(usize, i16)) -> usize {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let count = max.min(self.head().wrapping_sub(tail));
+        for i in 0..count {
+            f(
+                i,
+                self.samples[tail.wrapping_add(i) % self.samples.len()].load(Ordering::Relaxed),
+            );
+        }
+        self.tail.store(tail.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    /// Throw away up to `max` samples, called only by the consumer
+    pub fn skip(&self, max: usize) -> usize {
+        self.pop(max, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_around() {
+        let ring = Ring::new(4);
+        assert_eq!(ring.push(&[1, 2, 3]), 3);
+        assert_eq!(ring.skip(2), 2);
+        assert_eq!(ring.push(&[4, 5, 6, 7]), 3);
+        assert_eq!(ring.free(), 0);
+
+        let mut popped = Vec::new();
+        assert_eq!(ring.pop(10, |_, value| popped.push(value)), 4);
+        assert_eq!(popped, [3, 4, 5, 6]);
+        assert_eq!(ring.len(), 0);
+    }
+}
@@ -0,0 +1,51 @@
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+
+/// Values which one thread writes together and others read as a consistent snapshot,
+/// without either side taking a lock
+pub struct SeqLock<const N: usize> {
+    // Odd while a write is in progress
+    sequence: AtomicU64,
+    values: [AtomicU64; N],
+}
+
+impl<const N: usize> SeqLock<N> {
+    pub fn new(values: [u64; N]) -> Self {
+        Self {
+            sequence: AtomicU64::new(0),
+            values: values.map(AtomicU64::new),
+        }
+    }
+
+    /// Replace the values, only one thread may write
+    pub fn write(&self, values: [u64; N]) {
+        let sequence = self.sequence.load(Ordering::Relaxed);
+        self.sequence.store(sequence + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        for (atomic, value) in self.values.iter().zip(values) {
+            atomic.store(value, Ordering::Relaxed);
+        }
+        self.sequence.store(sequence + 2, Ordering::Release);
+    }
+
+    /// Read the values, or `None` if a write is in progress.
+    /// Audio callbacks use this to never wait for the writer.
+    pub fn try_read(&self) -> Option<[u64; N]> {
+        let sequence = self.sequence.load(Ordering::Acquire);
+        if sequence % 2 == 1 {
+            return None;
+        }
+        let values = std::array::from_fn(|i| self.values[i].load(Ordering::Relaxed));
+        fence(Ordering::Acquire);
+        (self.sequence.load(Ordering::Relaxed) == sequence).then_some(values)
+    }
+
+    /// Read the values, retrying until a write is not in progress
+    pub fn read(&self) -> [u64; N] {
+        loop {
+            if let Some(values) = self.try_read() {
+                return values;
+            }
+            std::hint::spin_loop();
+        }
+    }
+}
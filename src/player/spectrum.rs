@@ -0,0 +1,149 @@
+use super::levels::ChannelMode;
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use std::{
+    f32::consts::{PI, TAU},
+    sync::Arc,
+};
+
+/// Window function applied to the samples before FFT
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WindowFunction {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+}
+
+impl WindowFunction {
+    fn coefficient(self, n: usize, size: usize) -> f32 {
+        let x = n as f32 / size as f32;
+        match self {
+            Self::Rectangular => 1.,
+            Self::Hann => (PI * x).sin().powi(2),
+            Self::Hamming => 0.54 - 0.46 * (TAU * x).cos(),
+            Self::Blackman => 0.42 - 0.5 * (TAU * x).cos() + 0.08 * (2. * TAU * x).cos(),
+        }
+    }
+}
+
+/// Frequency range in Hz
+#[derive(Clone, Copy, Debug)]
+pub struct Band {
+    pub low: f32,
+    pub high: f32,
+}
+
+impl Band {
+    pub const SUB: Self = Self::new(20., 60.);
+    pub const BASS: Self = Self::new(60., 250.);
+    pub const LOW_MID: Self = Self::new(250., 500.);
+    pub const MID: Self = Self::new(500., 2000.);
+    pub const HIGH: Self = Self::new(2000., 6000.);
+    pub const AIR: Self = Self::new(6000., 20000.);
+
+    /// Default bands from sub to air, in order
+    pub const ALL: [Self; 6] = [
+        Self::SUB,
+        Self::BASS,
+        Self::LOW_MID,
+        Self::MID,
+        Self::HIGH,
+        Self::AIR,
+    ];
+
+    pub const fn new(low: f32, high: f32) -> Self {
+        Self { low, high }
+    }
+}
+
+pub struct Analyzer {
+    fft: Arc<dyn Fft<f32>>,
+    scratch: Vec<Complex<f32>>,
+    buffer: Vec<Complex<f32>>,
+    coefficients: Vec<f32>,
+    // Normalized magnitudes of the latest transform, up to the Nyquist frequency
+    magnitudes: Vec<f32>,
+    // Frame position and channel mode of the latest transform
+    position: Option<(u64, ChannelMode)>,
+}
+
+impl Analyzer {
+    pub fn new(size: usize, window_function: WindowFunction) -> Self {
+        let mut fft_planner = FftPlanner::new();
+        let fft = fft_planner.plan_fft_forward(size);
+        let scratch = vec![Complex::new(0., 0.); fft.get_inplace_scratch_len()];
+
+        Self {
+            fft,
+            scratch,
+            buffer: Vec::with_capacity(size),
+            coefficients: (0..size)
+                .map(|n| window_function.coefficient(n, size))
+                .collect(),
+            magnitudes: vec![0.; size / 2],
+            position: None,
+        }
+    }
+
+    /// FFT size in frames
+    pub fn size(&self) -> usize {
+        self.coefficients.len()
+    }
+
+    /// Normalized magnitudes of the latest transform, up to the Nyquist frequency
+    pub fn magnitudes(&self) -> &[f32] {
+        &self.magnitudes
+    }
+
+    pub fn position(&self) -> Option<(u64, ChannelMode)> {
+        self.position
+    }
+
+    /// Transform `size()` frames of interleaved samples taken from frame `position`
+    pub fn process(&mut self, position: u64, samples: &[i16], channels: usize, mode: ChannelMode) {
+        // Take the audio data slice and convert to windowed complex numbers
+        self.buffer.clear();
+        self.buffer
+            .extend(samples.chunks(channels).zip(&self.coefficients).map(
+                |(all_channels_sample, coefficient)| {
+                    Complex::new(coefficient * mode.sample(all_channels_sample), 0.)
+                },
+            ));
+
+        // Compute FFT
+        self.fft
+            .process_with_scratch(&mut self.buffer, &mut self.scratch);
+
+        // Normalize (see https://docs.rs/rustfft/5.0.1/rustfft/#normalization)
+        let normalization_scale = 1. / (self.size() as f32).sqrt();
+        for (magnitude, complex) in self.magnitudes.iter_mut().zip(&self.buffer) {
+            *magnitude = (complex * normalization_scale).norm();
+        }
+
+        self.position = Some((position, mode));
+    }
+
+    /// Average magnitude of the bins covering `band`
+    pub fn band(&self, sample_rate: u32, band: Band) -> f32 {
+        let freq_per_bin = sample_rate as f32 / self.size() as f32;
+        let start = ((band.low / freq_per_bin).floor() as usize).min(self.magnitudes.len() - 1);
+        let end =
+            ((band.high / freq_per_bin).ceil() as usize).clamp(start + 1, self.magnitudes.len());
+        self.magnitudes[start..end].iter().sum::<f32>() / (end - start) as f32
+    }
+
+    /// Split `range` to `out.len()` logarithmically spaced bands and write their magnitudes
+    pub fn log_spectrum(&self, sample_rate: u32, range: Band, out: &mut [f32]) {
+        let ratio = range.high / range.low;
+        let bins = out.len() as f32;
+        for (i, value) in out.iter_mut().enumerate() {
+            *value = self.band(
+                sample_rate,
+                Band::new(
+                    range.low * ratio.powf(i as f32 / bins),
+                    range.low * ratio.powf((i + 1) as f32 / bins),
+                ),
+            );
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Audio output health counters, see [`super::Player::audio_stats`]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct AudioStats {
+    /// Output callbacks run
+    pub callbacks: u64,
+    /// Callbacks which ran out of decoded audio during playback
+    pub underruns: u64,
+    /// Callbacks which came later than the previous buffer lasted, likely device xruns
+    pub late_buffers: u64,
+    /// Errors reported by the audio device
+    pub errors: u64,
+    /// Longest time spent in a callback
+    pub max_callback: Duration,
+    /// Time spent in all callbacks
+    pub total_callback: Duration,
+}
+
+impl AudioStats {
+    /// Average time spent in a callback
+    pub fn avg_callback(&self) -> Duration {
+        self.total_callback
+            .checked_div(self.callbacks.try_into().unwrap_or(u32::MAX))
+            .unwrap_or_default()
+    }
+}
+
+/// Counters behind [`AudioStats`], updated by audio callbacks without locking
+#[derive(Default)]
+pub struct AudioCounters {
+    callbacks: AtomicU64,
+    underruns: AtomicU64,
+    late_buffers: AtomicU64,
+    errors: AtomicU64,
+    // Nanoseconds
+    max_callback: AtomicU64,
+    total_callback: AtomicU64,
+}
+
+impl AudioCounters {
+    /// Record a callback's results
+    pub fn add_callback(&self, duration: Duration, underrun: bool, late: bool) {
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.callbacks.fetch_add(1, Ordering::Relaxed);
+        self.underruns
+            .fetch_add(u64::from(underrun), Ordering::Relaxed);
+        self.late_buffers
+            .fetch_add(u64::from(late), Ordering::Relaxed);
+        self.max_callback.fetch_max(nanos, Ordering::Relaxed);
+        self.total_callback.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    pub fn add_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> AudioStats {
+        AudioStats {
+            callbacks: self.callbacks.load(Ordering::Relaxed),
+            underruns: self.underruns.load(Ordering::Relaxed),
+            late_buffers: self.late_buffers.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            max_callback: Duration::from_nanos(self.max_callback.load(Ordering::Relaxed)),
+            total_callback: Duration::from_nanos(self.total_callback.load(Ordering::Relaxed)),
+        }
+    }
+}
@@ -0,0 +1,338 @@
+use super::{convert::Converter, decoder::Decoder, ring::Ring, seqlock::SeqLock};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    time::Duration,
+};
+
+// How often the decoder thread checks for room in a full buffer
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Requests from the render thread to the decoder thread, the audio callback never locks these
+#[derive(Default)]
+struct Control {
+    // Output sample position, playback rate and whether to keep pitch to restart decoding from
+    seek: Option<(usize, f64, bool)>,
+    // Output sample positions where decoding jumps from the end back to the start
+    loop_region: Option<(usize, usize)>,
+    quit: bool,
+}
+
+/// Ring buffer which a decoder thread keeps filled ahead of the playback position
+pub struct Stream {
+    ring: Ring,
+    control: Mutex<Control>,
+    wakeup: Condvar,
+    // Incremented on seek, so that samples decoded for an old position get thrown away
+    generation: AtomicU64,
+    // Generation, ring index and interleaved output sample position where its samples start
+    segment: SeqLock<3>,
+    end: AtomicBool,
+}
+
+impl Stream {
+    /// Spawn a decoder thread, `capacity` is the buffer size in interleaved output samples
+    pub fn spawn(decoder: Box<dyn Decoder>, converter: Converter, capacity: usize) -> Arc<Self> {
+        let stream = Arc::new(Self {
+            ring: Ring::new(capacity),
+            control: Mutex::new(Control::default()),
+            wakeup: Condvar::new(),
+            generation: AtomicU64::new(0),
+            segment: SeqLock::new([0; 3]),
+            end: AtomicBool::new(false),
+        });
+
+        let thread_stream = stream.clone();
+        std::thread::spawn(move || thread_stream.run(decoder, converter));
+
+        stream
+    }
+
+    fn seek_decoder(decoder: &mut dyn Decoder, converter: &mut Converter, pos: usize) {
+        let frame = converter.source_frame((pos / converter.channels()) as u64);
+        if let Err(e) = decoder.seek(frame as u64) {
+            log::error!("{:?}", e);
+        }
+        converter.reset(frame.fract());
+    }
+
+    fn run(&self, mut decoder: Box<dyn Decoder>, mut converter: Converter) {
+        let mut packet = Vec::new();
+        let mut converted = Vec::new();
+        // Converted samples which didn't fit in the ring yet
+        let mut pending = VecDeque::new();
+        // Output sample position in the track of the next converted sample
+        let mut track_pos = 0;
+        let mut more = true;
+
+        loop {
+            // Sleep until there's room in the ring or a seek to do
+            let (generation, seek, loop_region) = {
+                let control = self.control.lock().unwrap();
+                let (mut control, _) = self
+                    .wakeup
+                    .wait_timeout_while(control, POLL_INTERVAL, |control| {
+                        !control.quit
+                            && control.seek.is_none()
+                            && ((!more && pending.is_empty())
+                                || (!pending.is_empty() && self.ring.free() == 0))
+                    })
+                    .unwrap();
+                if control.quit {
+                    return;
+                }
+                (
+                    self.generation.load(Ordering::Relaxed),
+                    control.seek.take(),
+                    control.loop_region,
+                )
+            };
+
+            if let Some((pos, rate, preserve_pitch)) = seek {
+                converter.set_rate(rate, preserve_pitch);
+                track_pos = wrap(pos, loop_region);
+                Self::seek_decoder(decoder.as_mut(), &mut converter, track_pos);
+                pending.clear();
+                more = true;
+                self.end.store(false, Ordering::Relaxed);
+                self.segment
+                    .write([generation, self.ring.head() as u64, pos as u64]);
+            }
+
+            if pending.is_empty() && more {
+                packet.clear();
+                more = decoder.read(&mut packet).unwrap_or_else(|e| {
+                    log::error!("{:?}", e);
+                    false
+                });
+                converted.clear();
+                converter.process(&packet, &mut converted);
+                track_pos += converted.len();
+
+                // Continue from the loop start when reaching the loop end
+                if let Some((start, end)) = loop_region {
+                    if track_pos >= end || !more {
+                        let overshoot = track_pos.saturating_sub(end);
+                        converted.truncate(converted.len().saturating_sub(overshoot));
+                        track_pos = start;
+                        Self::seek_decoder(decoder.as_mut(), &mut converter, start);
+                        more = true;
+                    }
+                }
+                pending.extend(&converted);
+            }
+
+            let (front, back) = pending.as_slices();
+            let mut pushed = self.ring.push(front);
+            if pushed == front.len() {
+                pushed += self.ring.push(back);
+            }
+            pending.drain(..pushed);
+            if !more && pending.is_empty() {
+                self.end.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Start decoding from interleaved output sample position `pos` at playback `rate`,
+    /// time stretching instead of resampling if `preserve_pitch` is set
+    pub fn seek(&self, pos: usize, rate: f64, preserve_pitch: bool) {
+        let mut control = self.control.lock().unwrap();
+        self.generation.fetch_add(1, Ordering::Release);
+        self.end.store(false, Ordering::Relaxed);
+        control.seek = Some((pos, rate, preserve_pitch));
+        self.wakeup.notify_one();
+    }
+
+    /// Write samples starting from interleaved output sample position `pos` to `data`,
+    /// without blocking so that this can be called from an audio callback
+    ///
+    /// # Return value
+    ///
+    /// Returns how many samples were written. The rest of `data` is left untouched.
+    pub fn read<T: cpal::Sample>(&self, pos: usize, data: &mut [T]) -> usize {
+        // Nothing to play until the decoder has started from the latest seek
+        let generation = self.generation.load(Ordering::Acquire);
+        let (start, front) = match self.segment.try_read() {
+            Some([segment, start, front]) if segment == generation && pos as u64 >= front => {
+                (start as usize, front as usize)
+            }
+            _ => return 0,
+        };
+
+        // Skip samples from before the seek and ones which were not decoded in time for
+        // their playback
+        let index = start.wrapping_add(pos - front);
+        let late = index.wrapping_sub(self.ring.tail());
+        if (late as isize) > 0 {
+            self.ring.skip(late);
+        }
+        if self.ring.tail() != index {
+            return 0;
+        }
+
+        self.ring.pop(data.len(), |i, value| {
+            data[i] = cpal::Sample::from(&value);
+        })
+    }
+
+    /// Set output sample positions to loop between, takes effect on the next seek
+    pub fn set_loop(&self, region: Option<(usize, usize)>) {
+        self.control.lock().unwrap().loop_region = region;
+    }
+
+    /// Whether the decoder has reached the end of the track
+    pub fn is_end(&self) -> bool {
+        self.end.load(Ordering::Relaxed)
+    }
+
+    /// Stop the decoder thread
+    pub fn quit(&self) {
+        self.control.lock().unwrap().quit = true;
+        self.wakeup.notify_one();
+    }
+}
+
+/// Map a position past the loop end back into the loop region
+pub fn wrap<T>(pos: T, loop_region: Option<(T, T)>) -> T
+where
+    T: Copy
+        + PartialOrd
+        + std::ops::Add<Output = T>
+        + std::ops::Sub<Output = T>
+        + std::ops::Rem<Output = T>,
+{
+    match loop_region {
+        Some((start, end)) if pos >= end => start + (pos - start) % (end - start),
+        _ => pos,
+    }
+}
+
+/// Random access to decoded samples, for analysis on the render thread
+pub struct Window {
+    decoder: Box<dyn Decoder>,
+    samples: VecDeque<i16>,
+    // Frame position of samples[0]
+    start: u64,
+    end: bool,
+}
+
+impl Window {
+    pub fn new(decoder: Box<dyn Decoder>) -> Self {
+        Self {
+            decoder,
+            samples: VecDeque::new(),
+            start: 0,
+            end: false,
+        }
+    }
+
+    /// Get up to `frames` frames of interleaved samples starting from `start`
+    pub fn get(&mut self, start: u64, frames: usize) -> &[i16] {
+        let channels = usize::from(self.decoder.channels());
+        let cached_end = self.start + (self.samples.len() / channels) as u64;
+
+        // Going backwards or past the decoded data needs a seek, otherwise keep decoding forward
+        if start < self.start || start > cached_end {
+            self.samples.clear();
+            self.start = start;
+            self.end = false;
+            if let Err(e) = self.decoder.seek(start) {
+                log::error!("{:?}", e);
+                self.end = true;
+            }
+        }
+        self.samples
+            .drain(..(start - self.start) as usize * channels);
+        self.start = start;
+
+        let mut packet = Vec::new();
+        while !self.end && self.samples.len() < frames * channels {
+            packet.clear();
+            self.end = !self.decoder.read(&mut packet).unwrap_or_else(|e| {
+                log::error!("{:?}", e);
+                false
+            });
+            self.samples.extend(&packet);
+        }
+
+        let len = self.samples.len().min(frames * channels);
+        &self.samples.make_contiguous()[..len]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    const LEN: u64 = 20000;
+
+    /// Mono track where every sample is its frame number
+    struct Counter {
+        position: u64,
+    }
+
+    impl Decoder for Counter {
+        fn sample_rate(&self) -> u32 {
+            48000
+        }
+
+        fn channels(&self) -> u8 {
+            1
+        }
+
+        fn len_frames(&self) -> u64 {
+            LEN
+        }
+
+        fn seek(&mut self, frame: u64) -> Result<()> {
+            self.position = frame;
+            Ok(())
+        }
+
+        fn read(&mut self, out: &mut Vec<i16>) -> Result<bool> {
+            let end = (self.position + 100).min(LEN);
+            out.extend((self.position..end).map(|frame| frame as i16));
+            self.position = end;
+            Ok(end < LEN)
+        }
+
+        fn try_clone(&self) -> Result<Box<dyn Decoder>> {
+            Ok(Box::new(Self { position: 0 }))
+        }
+    }
+
+    /// Read `len` samples from `pos`, waiting for the decoder thread
+    fn read(stream: &Stream, pos: usize, len: usize) -> Vec<i16> {
+        let mut data = vec![0i16; len];
+        for _ in 0..1000 {
+            if stream.read(pos, &mut data) == len {
+                return data;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        panic!("Stream didn't decode position {}", pos);
+    }
+
+    #[test]
+    fn reads_from_seek_position() {
+        let stream = Stream::spawn(
+            Box::new(Counter { position: 0 }),
+            Converter::new(48000, 1, 48000, 1),
+            1000,
+        );
+        assert_eq!(read(&stream, 0, 10), (0..10).collect::<Vec<_>>());
+        // Skips samples which were not played in time
+        assert_eq!(read(&stream, 500, 10), (500..510).collect::<Vec<_>>());
+
+        stream.seek(12345, 1., false);
+        assert_eq!(read(&stream, 12345, 10), (12345..12355).collect::<Vec<_>>());
+        stream.seek(100, 1., false);
+        assert_eq!(read(&stream, 110, 10), (110..120).collect::<Vec<_>>());
+        stream.quit();
+    }
+}
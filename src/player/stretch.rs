@@ -0,0 +1,160 @@
+use std::collections::VecDeque;
+
+// Output frames between grains, grains are twice as long and overlap by half
+const HOP: usize = 1024;
+// How far from its nominal position a grain may start to line up with the previous one
+const SEARCH: usize = 256;
+
+/// Time stretching by waveform similarity overlap-add (WSOLA), which changes the speed
+/// of interleaved samples without changing their pitch
+pub struct Stretch {
+    channels: usize,
+    rate: f64,
+    window: Vec<f32>,
+    input: VecDeque<f32>,
+    // Nominal start of the next grain in frames from input[0]
+    pos: f64,
+    // Second half of the last grain, windowed for adding and as mono for matching
+    overlap: Vec<f32>,
+    tail: Vec<f32>,
+}
+
+impl Stretch {
+    pub fn new(channels: usize, rate: f64) -> Self {
+        Self {
+            channels,
+            rate,
+            // Hann window, overlapping halves sum to 1
+            window: (0..HOP * 2)
+                .map(|i| {
+                    (std::f32::consts::PI * i as f32 / (HOP * 2) as f32)
+                        .sin()
+                        .powi(2)
+                })
+                .collect(),
+            input: VecDeque::new(),
+            pos: 0.,
+            overlap: Vec::new(),
+            tail: Vec::new(),
+        }
+    }
+
+    /// Restart after a seek, the first grain fades in
+    pub fn reset(&mut self) {
+        self.input.clear();
+        self.pos = 0.;
+        self.overlap.clear();
+        self.tail.clear();
+    }
+
+    fn frames(&self) -> usize {
+        self.input.len() / self.channels
+    }
+
+    fn mono(&self, frame: usize) -> f32 {
+        let start = frame * self.channels;
+        (start..start + self.channels).map(|i| self.input[i]).sum()
+    }
+
+    /// Grain start near `nominal` which continues the last grain most smoothly
+    fn best_start(&self, nominal: usize) -> usize {
+        if self.tail.is_empty() {
+            return nominal;
+        }
+        (nominal.saturating_sub(SEARCH)..=nominal + SEARCH)
+            .map(|start| {
+                let correlation: f32 = self
+                    .tail
+                    .iter()
+                    .enumerate()
+                    .map(|(i, value)| value * self.mono(start + i))
+                    .sum();
+                (start, correlation)
+            })
+            .fold((nominal, f32::MIN), |best, candidate| {
+                if candidate.1 > best.1 {
+                    candidate
+                } else {
+                    best
+                }
+            })
+            .0
+    }
+
+    /// Stretch `input` and append the results to `output`
+    pub fn process(&mut self, input: &[i16], output: &mut Vec<i16>) {
+        self.input
+            .extend(input.iter().map(|&sample| f32::from(sample)));
+
+        // Each grain needs its whole search range decoded
+        while self.frames() >= self.pos as usize + SEARCH + HOP * 2 {
+            let start = self.best_start(self.pos as usize);
+            self.overlap.resize(HOP * self.channels, 0.);
+            for i in 0..HOP {
+                for channel in 0..self.channels {
+                    let value = self.overlap[i * self.channels + channel]
+                        + self.window[i] * self.input[(start + i) * self.channels + channel];
+                    output.push(value.clamp(i16::MIN.into(), i16::MAX.into()) as i16);
+                }
+            }
+
+            self.overlap.clear();
+            self.tail.clear();
+            for i in HOP..HOP * 2 {
+                let frame = (start + i) * self.channels;
+                self.overlap
+                    .extend((frame..frame + self.channels).map(|j| self.window[i] * self.input[j]));
+                self.tail.push(self.mono(start + i));
+            }
+
+            // Drop input which no later grain can start from
+            self.pos += HOP as f64 * self.rate;
+            let consumed = (self.pos as usize).saturating_sub(SEARCH);
+            self.input.drain(..consumed * self.channels);
+            self.pos -= consumed as f64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: usize = 48000;
+    const HZ: f32 = 440.;
+
+    /// Frequency from upward zero crossings
+    fn frequency(samples: &[i16]) -> f32 {
+        let crossings = samples
+            .windows(2)
+            .filter(|pair| pair[0] < 0 && pair[1] >= 0)
+            .count();
+        crossings as f32 * RATE as f32 / samples.len() as f32
+    }
+
+    #[test]
+    fn scales_length_and_keeps_pitch() {
+        let input: Vec<i16> = (0..RATE * 4)
+            .map(|i| (16000. * (i as f32 / RATE as f32 * HZ * std::f32::consts::TAU).sin()) as i16)
+            .collect();
+        for rate in [0.5, 2.] {
+            let mut stretch = Stretch::new(1, rate);
+            let mut output = Vec::new();
+            for packet in input.chunks(1000) {
+                stretch.process(packet, &mut output);
+            }
+
+            // Grains are only made once their search range is available
+            let expected = input.len() as f64 / rate;
+            assert!(
+                (output.len() as f64 - expected).abs() < (HOP * 3) as f64 / rate,
+                "rate {}: {} frames",
+                rate,
+                output.len()
+            );
+            // Skip the first grain which fades in
+            let hz = frequency(&output[HOP..]);
+            assert!((hz / HZ - 1.).abs() < 0.01, "rate {}: {} Hz", rate, hz);
+        }
+    }
+}
@@ -0,0 +1,366 @@
+use super::decoder::Decoder;
+use anyhow::{anyhow, Context, Result};
+use std::{collections::HashMap, sync::Arc};
+
+const SAMPLE_RATE: u32 = 48000;
+const CHANNELS: u8 = 2;
+// Frames decoded per read
+const BLOCK: usize = 1024;
+// Kick pitch starts this many times higher and falls with this time constant
+const KICK_SWEEP: f32 = 4.;
+const KICK_SWEEP_SECS: f32 = 0.03;
+
+#[derive(Clone, Copy)]
+enum Waveform {
+    Sine,
+    Square,
+    Saw,
+    Triangle,
+    Noise,
+    Kick,
+}
+
+impl Waveform {
+    fn parse(name: &str) -> Result<Self> {
+        match name {
+            "sine" => Ok(Self::Sine),
+            "square" => Ok(Self::Square),
+            "saw" => Ok(Self::Saw),
+            "triangle" => Ok(Self::Triangle),
+            "noise" => Ok(Self::Noise),
+            "kick" => Ok(Self::Kick),
+            _ => Err(anyhow!("Unknown waveform {}", name)),
+        }
+    }
+}
+
+struct Instrument {
+    waveform: Waveform,
+    // Envelope times in seconds and sustain level from 0 to 1
+    attack: f32,
+    decay: f32,
+    sustain: f32,
+    release: f32,
+    volume: f32,
+}
+
+impl Instrument {
+    /// Envelope level `t` seconds after note on, when the note is released at `held` seconds
+    fn level(&self, t: f32, held: f32) -> f32 {
+        let before_release = |t: f32| {
+            if t < self.attack {
+                t / self.attack
+            } else if t < self.attack + self.decay {
+                1. - (1. - self.sustain) * (t - self.attack) / self.decay
+            } else {
+                self.sustain
+            }
+        };
+        if t < held {
+            before_release(t)
+        } else if self.release > 0. {
+            before_release(held) * (1. - (t - held) / self.release).max(0.)
+        } else {
+            0.
+        }
+    }
+}
+
+struct Note {
+    instrument: usize,
+    hz: f32,
+    // Frames from song start to note on and note off
+    start: usize,
+    end: usize,
+}
+
+/// Frequency of a note name like `C4`, `F#2` or `Bb3`
+fn note_hz(name: &str) -> Result<f32> {
+    let mut chars = name.chars();
+    let semitone = match chars.next() {
+        Some('C') => 0,
+        Some('D') => 2,
+        Some('E') => 4,
+        Some('F') => 5,
+        Some('G') => 7,
+        Some('A') => 9,
+        Some('B') => 11,
+        _ => return Err(anyhow!("Invalid note {}", name)),
+    };
+    let rest = chars.as_str();
+    let (semitone, octave) = match rest.strip_prefix('#') {
+        Some(octave) => (semitone + 1, octave),
+        None => match rest.strip_prefix('b') {
+            Some(octave) => (semitone - 1, octave),
+            None => (semitone, rest),
+        },
+    };
+    let octave: i32 = octave
+        .parse()
+        .with_context(|| format!("Invalid octave in note {}", name))?;
+    let midi = (octave + 1) * 12 + semitone;
+    Ok(440. * 2f32.powf((midi - 69) as f32 / 12.))
+}
+
+/// A song made of instruments and patterns, parsed from text like this:
+///
+/// ```text
+/// # Tempo and rows per beat
+/// bpm 125
+/// rows 4
+/// # instrument name waveform attack decay sustain release volume
+/// instrument bass saw 0.005 0.2 0.5 0.1 0.5
+/// instrument kick kick 0.001 0.3 0 0.05 0.8
+/// # pattern name instrument, then a note, `.` to hold or `-` for note off on each row
+/// pattern b1 bass C2 . - . C2 . G1 .
+/// pattern k1 kick C3 . . . C3 . . .
+/// # Patterns which are played together, each line lasts as long as its longest pattern
+/// play b1 k1
+/// play b1 k1
+/// ```
+///
+/// Waveforms are sine, square, saw, triangle, noise and kick.
+struct Song {
+    instruments: Vec<Instrument>,
+    notes: Vec<Note>,
+    len_frames: usize,
+}
+
+impl Song {
+    fn parse(text: &str) -> Result<Self> {
+        let mut bpm = 120.;
+        let mut rows_per_beat = 4.;
+        let mut instruments = Vec::new();
+        let mut instrument_names = HashMap::new();
+        // Instrument and row values of each pattern
+        let mut patterns: HashMap<&str, (usize, Vec<&str>)> = HashMap::new();
+        let mut notes: Vec<Note> = Vec::new();
+        // Frames from song start to the next play line, each line uses the tempo set before it
+        let mut line_start = 0f64;
+
+        for (i, line) in text.lines().enumerate() {
+            let words: Vec<&str> = line
+                .split('#')
+                .next()
+                .unwrap_or_default()
+                .split_whitespace()
+                .collect();
+            let parse_f32 = |word: Option<&&str>| -> Result<f32> {
+                word.context("Missing value")?
+                    .parse()
+                    .context("Invalid number")
+            };
+
+            (|| -> Result<()> {
+                match words.as_slice() {
+                    [] => {}
+                    ["bpm", ..] => bpm = parse_f32(words.get(1))?,
+                    ["rows", ..] => rows_per_beat = parse_f32(words.get(1))?,
+                    ["instrument", name, waveform, ..] => {
+                        instrument_names.insert(*name, instruments.len());
+                        instruments.push(Instrument {
+                            waveform: Waveform::parse(waveform)?,
+                            attack: parse_f32(words.get(3))?,
+                            decay: parse_f32(words.get(4))?,
+                            sustain: parse_f32(words.get(5))?,
+                            release: parse_f32(words.get(6))?,
+                            volume: parse_f32(words.get(7))?,
+                        });
+                    }
+                    ["pattern", name, instrument, values @ ..] => {
+                        let instrument = *instrument_names
+                            .get(instrument)
+                            .with_context(|| format!("Unknown instrument {}", instrument))?;
+                        patterns.insert(*name, (instrument, values.to_vec()));
+                    }
+                    ["play", names @ ..] => {
+                        let row_frames =
+                            f64::from(SAMPLE_RATE) * 60. / f64::from(bpm * rows_per_beat);
+                        let mut len = 0;
+                        for name in names {
+                            let (instrument, values) = patterns
+                                .get(name)
+                                .with_context(|| format!("Unknown pattern {}", name))?;
+                            len = len.max(values.len());
+
+                            let frame = |r: usize| (line_start + r as f64 * row_frames) as usize;
+                            // Index of the note which is playing in this pattern
+                            let mut playing: Option<usize> = None;
+                            for (r, value) in values.iter().enumerate() {
+                                if *value == "." {
+                                    continue;
+                                }
+                                // Note on or off ends the previous note of the pattern
+                                let at = frame(r);
+                                if let Some(index) = playing.take() {
+                                    notes[index].end = at;
+                                }
+                                if *value != "-" {
+                                    playing = Some(notes.len());
+                                    notes.push(Note {
+                                        instrument: *instrument,
+                                        hz: note_hz(value)?,
+                                        start: at,
+                                        end: frame(values.len()),
+                                    });
+                                }
+                            }
+                        }
+                        line_start += len as f64 * row_frames;
+                    }
+                    [keyword, ..] => return Err(anyhow!("Unknown keyword {}", keyword)),
+                }
+                Ok(())
+            })()
+            .with_context(|| format!("Line {}", i + 1))?;
+        }
+
+        Ok(Self {
+            instruments,
+            notes,
+            len_frames: line_start as usize,
+        })
+    }
+
+    /// Mix all notes into interleaved samples
+    fn render(&self) -> Vec<i16> {
+        // Leave room for release tails after the last row
+        let tail = self
+            .instruments
+            .iter()
+            .map(|instrument| instrument.release)
+            .fold(0., f32::max);
+        let len = self.len_frames + (tail * SAMPLE_RATE as f32) as usize;
+        let mut mix = vec![0f32; len];
+        let mut noise = 0x12345678u32;
+
+        for note in &self.notes {
+            let instrument = &self.instruments[note.instrument];
+            let held = (note.end - note.start) as f32 / SAMPLE_RATE as f32;
+            let mut phase = 0f32;
+            for (i, out) in mix[note.start..].iter_mut().enumerate() {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                if t >= held + instrument.release {
+                    break;
+                }
+
+                let value = match instrument.waveform {
+                    Waveform::Sine | Waveform::Kick => (phase * std::f32::consts::TAU).sin(),
+                    Waveform::Square if phase < 0.5 => 1.,
+                    Waveform::Square => -1.,
+                    Waveform::Saw => phase * 2. - 1.,
+                    Waveform::Triangle => 1. - 4. * (phase - 0.5).abs(),
+                    Waveform::Noise => {
+                        noise ^= noise << 13;
+                        noise ^= noise >> 17;
+                        noise ^= noise << 5;
+                        noise as f32 / u32::MAX as f32 * 2. - 1.
+                    }
+                };
+                *out += value * instrument.level(t, held) * instrument.volume;
+
+                let hz = match instrument.waveform {
+                    Waveform::Kick => {
+                        note.hz * (1. + (KICK_SWEEP - 1.) * (-t / KICK_SWEEP_SECS).exp())
+                    }
+                    _ => note.hz,
+                };
+                phase = (phase + hz / SAMPLE_RATE as f32).fract();
+            }
+        }
+
+        mix.iter()
+            .flat_map(|&value| {
+                let sample = (value * i16::MAX as f32).clamp(i16::MIN as f32, i16::MAX as f32);
+                [sample as i16; CHANNELS as usize]
+            })
+            .collect()
+    }
+}
+
+/// Music synthesized from a song description when loading
+pub struct SynthDecoder {
+    samples: Arc<[i16]>,
+    position: usize,
+}
+
+impl SynthDecoder {
+    pub fn new(data: &[u8]) -> Result<Self> {
+        let text = std::str::from_utf8(data).context("Song is not valid UTF-8")?;
+        let song = Song::parse(text)?;
+        log::info!(
+            "Synthesizing {} notes with {} instruments",
+            song.notes.len(),
+            song.instruments.len()
+        );
+        Ok(Self {
+            samples: song.render().into(),
+            position: 0,
+        })
+    }
+}
+
+impl Decoder for SynthDecoder {
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn channels(&self) -> u8 {
+        CHANNELS
+    }
+
+    fn len_frames(&self) -> u64 {
+        (self.samples.len() / usize::from(CHANNELS)) as u64
+    }
+
+    fn seek(&mut self, frame: u64) -> Result<()> {
+        self.position = (frame as usize * usize::from(CHANNELS)).min(self.samples.len());
+        Ok(())
+    }
+
+    fn read(&mut self, out: &mut Vec<i16>) -> Result<bool> {
+        let end = (self.position + BLOCK * usize::from(CHANNELS)).min(self.samples.len());
+        out.extend_from_slice(&self.samples[self.position..end]);
+        self.position = end;
+        Ok(end < self.samples.len())
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn Decoder>> {
+        Ok(Box::new(Self {
+            samples: self.samples.clone(),
+            position: 0,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tempo_changes_keep_earlier_rows() {
+        let song = Song::parse(
+            "bpm 120
+            rows 4
+            instrument lead square 0 0 1 0.1 0.5
+            pattern a lead C4 . A4 -
+            play a
+            bpm 240
+            play a",
+        )
+        .unwrap();
+
+        // Rows last 6000 frames at 120 bpm and 3000 at 240
+        let bounds: Vec<_> = song
+            .notes
+            .iter()
+            .map(|note| (note.start, note.end))
+            .collect();
+        assert_eq!(
+            bounds,
+            [(0, 12000), (12000, 18000), (24000, 30000), (30000, 33000)]
+        );
+        assert_eq!(song.len_frames, 36000);
+        assert_eq!(song.render().len(), (36000 + 4800) * usize::from(CHANNELS));
+    }
+}
@@ -0,0 +1,269 @@
+use super::decoder::Decoder;
+use anyhow::Result;
+use module::Module;
+use replayer::{Replayer, SAMPLE_RATE};
+use std::sync::Arc;
+
+mod module;
+mod replayer;
+mod xm;
+
+/// Song position of a row in a tracker module
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct TrackerPosition {
+    /// Index in the order list
+    pub order: u8,
+    /// Pattern at the order, `None` when the file doesn't have it and empty rows are played
+    pub pattern: Option<u8>,
+    pub row: u8,
+}
+
+/// Where each row of a module starts when played from the beginning
+pub struct Timeline {
+    rows: Vec<(u64, TrackerPosition)>,
+    len_frames: u64,
+}
+
+impl Timeline {
+    fn new(module: &Arc<Module>) -> Self {
+        let mut replayer = Replayer::new(module.clone());
+        let mut rows = Vec::new();
+        let mut frame = 0;
+        while !replayer.ended {
+            let position = TrackerPosition {
+                order: replayer.order as u8,
+                pattern: module
+                    .pattern(replayer.order)
+                    .map(|_| module.orders[replayer.order]),
+                row: replayer.row as u8,
+            };
+            if replayer.next_tick() {
+                rows.push((frame, position));
+            }
+            frame += replayer.tick_frames;
+        }
+        Self {
+            rows,
+            len_frames: frame,
+        }
+    }
+
+    /// Start and end frames of row `i`
+    fn bounds(&self, i: usize) -> Option<(f64, f64)> {
+        let start = self.rows.get(i)?.0;
+        let end = self
+            .rows
+            .get(i + 1)
+            .map(|&(start, _)| start)
+            .unwrap_or(self.len_frames);
+        Some((start as f64, end as f64))
+    }
+
+    /// Index of the row playing at `frame` and the start and end frames of that row
+    fn find(&self, frame: f64) -> Option<(usize, f64, f64)> {
+        let i = self
+            .rows
+            .partition_point(|&(start, _)| start as f64 <= frame)
+            .saturating_sub(1);
+        let (start, end) = self.bounds(i)?;
+        Some((i, start, end))
+    }
+
+    pub fn position(&self, at_secs: f32) -> Option<TrackerPosition> {
+        let (i, _, _) = self.find(f64::from(at_secs) * f64::from(SAMPLE_RATE))?;
+        Some(self.rows[i].1)
+    }
+
+    /// Rows played before `at_secs`, with the fraction of the current row
+    pub fn row(&self, at_secs: f32) -> f32 {
+        let frame = f64::from(at_secs) * f64::from(SAMPLE_RATE);
+        match self.find(frame) {
+            Some((i, start, end)) if end > start => {
+                (i as f64 + (frame - start) / (end - start)) as f32
+            }
+            Some((i, _, _)) => i as f32,
+            None => 0.,
+        }
+    }
+
+    /// Seconds when a row given by [`Timeline::row`] is played
+    pub fn secs(&self, row: f32) -> f32 {
+        let i = (row.max(0.) as usize).min(self.rows.len().saturating_sub(1));
+        match self.bounds(i) {
+            Some((start, end)) => {
+                ((start + (end - start) * f64::from(row - i as f32)) / f64::from(SAMPLE_RATE))
+                    as f32
+            }
+            None => 0.,
+        }
+    }
+}
+
+/// Renders FastTracker 2 modules, the sequencer is rerun from the start on seeks
+pub struct TrackerDecoder {
+    module: Arc<Module>,
+    timeline: Arc<Timeline>,
+    replayer: Replayer,
+}
+
+impl TrackerDecoder {
+    pub fn new(data: &[u8]) -> Result<Self> {
+        let module = Arc::new(xm::parse(data)?);
+        let timeline = Arc::new(Timeline::new(&module));
+        log::info!(
+            "Module has {} channels and {} rows",
+            module.channels,
+            timeline.rows.len()
+        );
+        Ok(Self {
+            replayer: Replayer::new(module.clone()),
+            module,
+            timeline,
+        })
+    }
+}
+
+impl Decoder for TrackerDecoder {
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn channels(&self) -> u8 {
+        2
+    }
+
+    fn len_frames(&self) -> u64 {
+        self.timeline.len_frames
+    }
+
+    fn seek(&mut self, frame: u64) -> Result<()> {
+        self.replayer = Replayer::new(self.module.clone());
+        let mut position = 0;
+        while position < frame {
+            if self.replayer.tick_frames == 0 {
+                if self.replayer.ended {
+                    break;
+                }
+                self.replayer.next_tick();
+            }
+            let frames = self.replayer.tick_frames.min(frame - position);
+            self.replayer.mix(frames, None);
+            self.replayer.tick_frames -= frames;
+            position += frames;
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, out: &mut Vec<i16>) -> Result<bool> {
+        if self.replayer.tick_frames == 0 {
+            if self.replayer.ended {
+                return Ok(false);
+            }
+            self.replayer.next_tick();
+        }
+        let frames = self.replayer.tick_frames;
+        self.replayer.mix(frames, Some(out));
+        self.replayer.tick_frames = 0;
+        Ok(true)
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn Decoder>> {
+        Ok(Box::new(Self {
+            module: self.module.clone(),
+            timeline: self.timeline.clone(),
+            replayer: Replayer::new(self.module.clone()),
+        }))
+    }
+
+    fn timeline(&self) -> Option<Arc<Timeline>> {
+        Some(self.timeline.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROW_SECS: f32 = 0.12;
+
+    /// Notes on the first rows of a module, which switches to speed 3 on row 32
+    fn decoder() -> TrackerDecoder {
+        let data = xm::tests::module(&[
+            (0, 0, [49, 1, 0, 0, 0]),
+            (5, 1, [54, 1, 0, 0, 0]),
+            (32, 2, [0, 0, 0, 0xf, 3]),
+            (40, 3, [49, 1, 0, 0x4, 0x20]),
+        ]);
+        TrackerDecoder::new(&data).unwrap()
+    }
+
+    fn read_all(decoder: &mut TrackerDecoder) -> Vec<i16> {
+        let mut out = Vec::new();
+        while decoder.read(&mut out).unwrap() {}
+        out
+    }
+
+    #[test]
+    fn timeline_follows_speed_changes() {
+        let timeline = decoder().timeline;
+        assert_eq!(timeline.rows.len(), 64);
+        assert_eq!(timeline.len_frames, (32 * 5760 + 32 * 2880) as u64);
+
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-4;
+        assert!(close(timeline.secs(0.), 0.));
+        assert!(close(timeline.secs(10.5), 10.5 * ROW_SECS));
+        assert!(close(timeline.secs(32.), 32. * ROW_SECS));
+        assert!(close(
+            timeline.secs(33.5),
+            32. * ROW_SECS + 1.5 * ROW_SECS / 2.
+        ));
+        for row in [0., 0.25, 10.5, 31.9, 32., 47.3, 63.5] {
+            assert!(close(timeline.row(timeline.secs(row)), row), "row {}", row);
+        }
+
+        assert_eq!(
+            timeline.position(timeline.secs(40.25)),
+            Some(TrackerPosition {
+                order: 0,
+                pattern: Some(0),
+                row: 40,
+            })
+        );
+    }
+
+    #[test]
+    fn missing_pattern_has_no_number() {
+        // Second order plays pattern 7, which isn't in the file
+        let mut data = xm::tests::module(&[]);
+        data[64] = 2;
+        data[81] = 7;
+        let timeline = TrackerDecoder::new(&data).unwrap().timeline;
+        assert_eq!(timeline.rows.len(), 128);
+        assert_eq!(
+            timeline.position(timeline.secs(70.5)),
+            Some(TrackerPosition {
+                order: 1,
+                pattern: None,
+                row: 6,
+            })
+        );
+    }
+
+    #[test]
+    fn seek_matches_straight_read() {
+        let straight = read_all(&mut decoder());
+        assert_eq!(straight.len() as u64, decoder().len_frames() * 2);
+        assert!(straight.iter().any(|&sample| sample != 0));
+
+        for frame in [0, 1000, 5760 * 3 + 17, 5760 * 32 + 100, 200_000] {
+            let mut decoder = decoder();
+            decoder.seek(frame).unwrap();
+            let seeked = read_all(&mut decoder);
+            assert!(
+                seeked == straight[frame as usize * 2..],
+                "seek to {}",
+                frame
+            );
+        }
+    }
+}
@@ -0,0 +1,373 @@
+/// Notes are numbered in semitones from C-0, a sample with no tuning plays C-5 at 8363 Hz
+pub const NOTE_COUNT: usize = 120;
+pub const MIDDLE_NOTE: f32 = 60.;
+const MIDDLE_RATE: f64 = 8363.;
+// Amiga period of the middle note, in the 1/4 period units which all periods use
+const MIDDLE_PERIOD: f32 = 1712.;
+
+/// How periods map to playback rates
+#[derive(Clone, Copy)]
+pub enum Frequencies {
+    /// Amiga periods in 1/4 period units, played at `clock / period` Hz
+    Amiga { clock: f64, min: f32, max: f32 },
+    /// Periods in 1/64 semitones
+    Linear,
+}
+
+impl Frequencies {
+    /// Period of a note, which may have a fraction from sample tuning
+    pub fn period(&self, note: f32) -> f32 {
+        match self {
+            Self::Amiga { .. } => MIDDLE_PERIOD * 2f32.powf((MIDDLE_NOTE - note) / 12.),
+            Self::Linear => (NOTE_COUNT as f32 - note) * 64.,
+        }
+    }
+
+    /// Period raised by `semitones`
+    pub fn transpose(&self, period: f32, semitones: f32) -> f32 {
+        match self {
+            Self::Amiga { .. } => period * 2f32.powf(-semitones / 12.),
+            Self::Linear => period - semitones * 64.,
+        }
+    }
+
+    /// Keep a period in the range of the original player
+    pub fn clamp(&self, period: f32) -> f32 {
+        match *self {
+            Self::Amiga { min, max, .. } => period.clamp(min, max),
+            Self::Linear => period.clamp(0., NOTE_COUNT as f32 * 2. * 64.),
+        }
+    }
+
+    /// Playback rate in Hz
+    pub fn rate(&self, period: f32) -> f64 {
+        match *self {
+            Self::Amiga { clock, .. } => clock / f64::from(period.max(1.)),
+            Self::Linear => {
+                let from_middle = NOTE_COUNT as f32 - MIDDLE_NOTE - period / 64.;
+                MIDDLE_RATE * 2f64.powf(f64::from(from_middle) / 12.)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum Key {
+    #[default]
+    None,
+    /// Semitones from C-0
+    Note(u8),
+    /// Release the note, letting envelopes finish
+    Off,
+}
+
+/// Volume column command
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum Volume {
+    #[default]
+    None,
+    Set(u8),
+    SlideUp(u8),
+    SlideDown(u8),
+    FineUp(u8),
+    FineDown(u8),
+    VibratoSpeed(u8),
+    Vibrato(u8),
+    /// Panning from 0 to 255
+    Panning(u8),
+    PanningSlideLeft(u8),
+    PanningSlideRight(u8),
+    /// Tone portamento with a speed in the units of [`Effect::TonePorta`]
+    TonePorta(u8),
+}
+
+/// Effect command, with the parameter as stored in the file unless noted
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum Effect {
+    #[default]
+    None,
+    Arpeggio(u8),
+    /// Slides by the parameter every tick but the first
+    PortaUp(u8),
+    PortaDown(u8),
+    FinePortaUp(u8),
+    FinePortaDown(u8),
+    ExtraFinePortaUp(u8),
+    ExtraFinePortaDown(u8),
+    TonePorta(u8),
+    Vibrato(u8),
+    /// Tone portamento or vibrato with a volume slide
+    TonePortaVolumeSlide(u8),
+    VibratoVolumeSlide(u8),
+    Tremolo(u8),
+    Tremor(u8),
+    /// Panning from 0 to 255
+    Panning(u8),
+    SampleOffset(u8),
+    /// Up by x and down by y every tick but the first
+    VolumeSlide(u8),
+    FineVolumeUp(u8),
+    FineVolumeDown(u8),
+    PositionJump(u8),
+    SetVolume(u8),
+    /// Row in the next pattern, decoded from decimal
+    PatternBreak(u8),
+    SetSpeed(u8),
+    SetTempo(u8),
+    /// Global volume from 0 to 64
+    GlobalVolume(u8),
+    GlobalVolumeSlide(u8),
+    /// Right by x and left by y every tick but the first
+    PanningSlide(u8),
+    /// Retrigger every y ticks
+    Retrigger(u8),
+    /// Retrigger every y ticks changing the volume by x
+    MultiRetrigger(u8),
+    PatternLoop(u8),
+    NoteCut(u8),
+    NoteDelay(u8),
+    /// Repeat the row this many times
+    PatternDelay(u8),
+    KeyOff(u8),
+    SetEnvelopePosition(u8),
+    VibratoWaveform(u8),
+    TremoloWaveform(u8),
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Note {
+    pub key: Key,
+    /// Instrument number starting from 1, 0 for none
+    pub instrument: u8,
+    pub volume: Volume,
+    pub effect: Effect,
+}
+
+pub struct Pattern {
+    pub rows: usize,
+    /// Notes of each row, `channels` per row
+    pub notes: Vec<Note>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Loop {
+    pub start: usize,
+    pub end: usize,
+    pub ping_pong: bool,
+}
+
+impl Loop {
+    /// Loop with a start and end in samples, if it's long enough to play
+    pub fn new(start: usize, end: usize, len: usize, ping_pong: bool) -> Option<Self> {
+        let end = end.min(len);
+        (start < end).then_some(Self {
+            start,
+            end,
+            ping_pong,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum Waveform {
+    #[default]
+    Sine,
+    RampDown,
+    RampUp,
+    Square,
+}
+
+impl Waveform {
+    /// Value from -1 to 1 at `pos` of a 256 step cycle
+    pub fn value(self, pos: u8) -> f32 {
+        match self {
+            Self::Sine => (f32::from(pos) / 128. * std::f32::consts::PI).sin(),
+            Self::RampDown => 1. - f32::from(pos) / 128.,
+            Self::RampUp => f32::from(pos) / 128. - 1.,
+            Self::Square if pos < 128 => 1.,
+            Self::Square => -1.,
+        }
+    }
+}
+
+/// Vibrato applied by samples on their own
+#[derive(Clone, Copy, Default)]
+pub struct AutoVibrato {
+    pub waveform: Waveform,
+    /// Ticks until full depth
+    pub sweep: u32,
+    /// Depth in period units
+    pub depth: f32,
+    /// Speed in 1/256 cycles per tick
+    pub rate: u8,
+}
+
+pub struct Sample {
+    pub data: Vec<f32>,
+    /// Default volume from 0 to 64
+    pub volume: u8,
+    /// Default panning from 0 to 1
+    pub panning: f32,
+    /// Semitones added to notes
+    pub tune: f32,
+    pub looping: Option<Loop>,
+    pub vibrato: AutoVibrato,
+}
+
+impl Sample {
+    pub fn new(data: Vec<f32>, volume: u8, tune: f32) -> Self {
+        Self {
+            data,
+            volume: volume.min(64),
+            panning: 0.5,
+            tune,
+            looping: None,
+            vibrato: AutoVibrato::default(),
+        }
+    }
+}
+
+pub struct Envelope {
+    /// Ticks and values, volumes from 0 to 1 and panning from -1 to 1
+    pub points: Vec<(u32, f32)>,
+    /// First and last point of loops
+    pub looping: Option<(usize, usize)>,
+    pub sustain: Option<(usize, usize)>,
+}
+
+impl Envelope {
+    /// Envelope from points if it's enabled and has some
+    pub fn new(
+        enabled: bool,
+        points: Vec<(u32, f32)>,
+        looping: Option<(usize, usize)>,
+        sustain: Option<(usize, usize)>,
+    ) -> Option<Self> {
+        let len = points.len();
+        let valid =
+            |range: Option<(usize, usize)>| range.filter(|&(start, end)| start <= end && end < len);
+        (enabled && len > 0).then(|| Self {
+            looping: valid(looping),
+            sustain: valid(sustain),
+            points,
+        })
+    }
+
+    pub fn value(&self, tick: u32) -> f32 {
+        let next = self.points.partition_point(|&(t, _)| t <= tick);
+        match (next.checked_sub(1), self.points.get(next)) {
+            (Some(i), Some(&(end, to))) => {
+                let (start, from) = self.points[i];
+                from + (to - from) * (tick - start) as f32 / (end - start) as f32
+            }
+            (Some(i), None) => self.points[i].1,
+            (None, _) => self.points[0].1,
+        }
+    }
+
+    /// Tick after `tick`, which loops back while the key is held or on a loop
+    pub fn next(&self, tick: u32, key_on: bool) -> u32 {
+        let tick = tick + 1;
+        let range = match self.sustain {
+            Some(sustain) if key_on => Some(sustain),
+            _ => self.looping,
+        };
+        match range {
+            Some((start, end)) if tick > self.points[end].0 => self.points[start].0,
+            _ => tick.min(self.end() + 1),
+        }
+    }
+
+    /// Whether `tick` is past the last point
+    pub fn ended(&self, tick: u32) -> bool {
+        self.looping.is_none() && tick > self.end()
+    }
+
+    fn end(&self) -> u32 {
+        self.points.last().map(|&(tick, _)| tick).unwrap_or(0)
+    }
+}
+
+pub struct Instrument {
+    /// Note and sample index played for each note
+    pub keymap: Vec<(u8, Option<usize>)>,
+    pub volume_envelope: Option<Envelope>,
+    pub panning_envelope: Option<Envelope>,
+    /// Subtracted from a fade volume of 65536 every tick after the note is released
+    pub fadeout: u32,
+}
+
+pub struct Module {
+    pub channels: usize,
+    pub instruments: Vec<Instrument>,
+    pub samples: Vec<Sample>,
+    /// Pattern of each position
+    pub orders: Vec<u8>,
+    pub patterns: Vec<Pattern>,
+    pub frequencies: Frequencies,
+    pub speed: u32,
+    pub tempo: u32,
+    /// Multiplier for mixing each channel
+    pub gain: f32,
+}
+
+impl Module {
+    pub fn note(&self, order: usize, row: usize, channel: usize) -> Note {
+        self.pattern(order)
+            .and_then(|pattern| pattern.notes.get(row * self.channels + channel))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Pattern at an order, if the file has it
+    pub fn pattern(&self, order: usize) -> Option<&Pattern> {
+        self.patterns.get(usize::from(*self.orders.get(order)?))
+    }
+
+    /// Rows in the pattern at an order, patterns which don't exist are empty
+    pub fn rows(&self, order: usize) -> usize {
+        self.pattern(order)
+            .map(|pattern| pattern.rows)
+            .unwrap_or(64)
+    }
+
+    /// Gain for mixing a module with a number of channels at full volume without clipping
+    /// as long as only some of them play at once
+    pub fn default_gain(channels: usize) -> f32 {
+        1. / (channels.max(1) as f32).sqrt()
+    }
+}
+
+/// Little endian readers for module data, failing on truncated files
+pub struct Reader<'a> {
+    pub data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn bytes(&self, start: usize, len: usize) -> anyhow::Result<&'a [u8]> {
+        use anyhow::Context;
+        self.data
+            .get(start..start.checked_add(len).context("Module file is truncated")?)
+            .context("Module file is truncated")
+    }
+
+    pub fn u8(&self, start: usize) -> anyhow::Result<u8> {
+        Ok(self.bytes(start, 1)?[0])
+    }
+
+    pub fn u16(&self, start: usize) -> anyhow::Result<u16> {
+        let b = self.bytes(start, 2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    pub fn u32(&self, start: usize) -> anyhow::Result<u32> {
+        let b = self.bytes(start, 4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// Bytes from `start` up to `len`, cut short at the end of the file
+    pub fn up_to(&self, start: usize, len: usize) -> &'a [u8] {
+        let start = start.min(self.data.len());
+        &self.data[start..start.saturating_add(len).min(self.data.len())]
+    }
+}
@@ -0,0 +1,746 @@
+use super::module::*;
+use std::sync::Arc;
+
+pub const SAMPLE_RATE: u32 = 48000;
+const VIBRATO_SINE: [u8; 32] = [
+    0, 24, 49, 74, 97, 120, 141, 161, 180, 197, 212, 224, 235, 244, 250, 253, 255, 253, 250, 244,
+    235, 224, 212, 197, 180, 161, 141, 120, 97, 74, 49, 24,
+];
+const FADE_MAX: u32 = 65536;
+
+/// Value of a vibrato or tremolo waveform from -255 to 255 at a position of a 64 step cycle
+fn waveform(waveform: u8, pos: u8) -> i32 {
+    let pos = pos % 64;
+    match waveform & 3 {
+        1 => 255 - i32::from(pos) * 8,
+        2 if pos < 32 => 255,
+        2 => -255,
+        _ if pos < 32 => i32::from(VIBRATO_SINE[usize::from(pos)]),
+        _ => -i32::from(VIBRATO_SINE[usize::from(pos & 31)]),
+    }
+}
+
+/// Playing note of an instrument
+#[derive(Clone, Default)]
+struct Voice {
+    instrument: Option<usize>,
+    sample: Option<usize>,
+    // Position in sample data in samples, and whether it's going backwards in a ping-pong loop
+    pos: f64,
+    backwards: bool,
+    playing: bool,
+    key_on: bool,
+    fading: bool,
+    fade: u32,
+    volume_envelope: u32,
+    panning_envelope: u32,
+    vibrato_pos: u8,
+    vibrato_ticks: u32,
+    // Period, volume and panning set by the channel, before envelopes
+    period: f32,
+    volume: f32,
+    pan: f32,
+    // What is mixed during this tick
+    gains: [f32; 2],
+    step: f64,
+}
+
+impl Voice {
+    fn start(&mut self, instrument: Option<usize>, sample: usize, pos: f64) {
+        self.instrument = instrument;
+        self.sample = Some(sample);
+        self.pos = pos;
+        self.backwards = false;
+        self.playing = true;
+        self.vibrato_pos = 0;
+        self.vibrato_ticks = 0;
+        self.restart_envelopes();
+    }
+
+    fn restart_envelopes(&mut self) {
+        self.key_on = true;
+        self.fading = false;
+        self.fade = FADE_MAX;
+        self.volume_envelope = 0;
+        self.panning_envelope = 0;
+    }
+
+    /// Let go of the note, which ends envelope sustain. Notes without a volume envelope are
+    /// cut and the rest fade out.
+    fn release(&mut self, module: &Module) {
+        self.key_on = false;
+        let envelope = self
+            .instrument
+            .and_then(|i| module.instruments.get(i))
+            .and_then(|instrument| instrument.volume_envelope.as_ref());
+        match envelope {
+            None => self.playing = false,
+            Some(_) => self.fading = true,
+        }
+    }
+
+    /// Apply envelopes and vibrato for the next tick
+    fn update(&mut self, module: &Module, global_volume: f32) {
+        let Some(sample) = self.sample.and_then(|s| module.samples.get(s)) else {
+            self.playing = false;
+            return;
+        };
+        let mut volume = self.volume * global_volume;
+        let mut pan = self.pan;
+        let mut period = self.period;
+
+        if let Some(instrument) = self.instrument.and_then(|i| module.instruments.get(i)) {
+            if let Some(envelope) = &instrument.volume_envelope {
+                volume *= envelope.value(self.volume_envelope);
+                if !self.key_on && envelope.ended(self.volume_envelope) {
+                    self.fading = true;
+                }
+                self.volume_envelope = envelope.next(self.volume_envelope, self.key_on);
+            }
+            if let Some(envelope) = &instrument.panning_envelope {
+                pan += envelope.value(self.panning_envelope) * (0.5 - (pan - 0.5).abs());
+                self.panning_envelope = envelope.next(self.panning_envelope, self.key_on);
+            }
+            volume *= self.fade as f32 / FADE_MAX as f32;
+            if self.fading {
+                self.fade = self.fade.saturating_sub(instrument.fadeout);
+                if self.fade == 0 {
+                    self.playing = false;
+                }
+            }
+        }
+
+        let vibrato = sample.vibrato;
+        if vibrato.depth > 0. {
+            let depth = if self.vibrato_ticks < vibrato.sweep {
+                vibrato.depth * self.vibrato_ticks as f32 / vibrato.sweep as f32
+            } else {
+                vibrato.depth
+            };
+            period += vibrato.waveform.value(self.vibrato_pos) * depth;
+            self.vibrato_pos = self.vibrato_pos.wrapping_add(vibrato.rate);
+            self.vibrato_ticks += 1;
+        }
+
+        let pan = pan.clamp(0., 1.);
+        let volume = volume.max(0.) * module.gain;
+        self.gains = [volume * (1. - pan), volume * pan];
+        let rate = module.frequencies.rate(module.frequencies.clamp(period));
+        self.step = rate / f64::from(SAMPLE_RATE);
+    }
+
+    /// Move by `amount` samples, wrapping around loops
+    fn advance(&mut self, sample: &Sample, amount: f64) {
+        let looping = sample.looping;
+        if !looping.map(|l| l.ping_pong).unwrap_or(false) {
+            self.backwards = false;
+        }
+        self.pos += if self.backwards { -amount } else { amount };
+
+        let Some(l) = looping else {
+            if self.pos >= sample.data.len() as f64 {
+                self.playing = false;
+            }
+            return;
+        };
+        let (start, end) = (l.start as f64, l.end as f64);
+        let len = end - start;
+        if !l.ping_pong {
+            if self.pos >= end {
+                self.pos = start + (self.pos - end) % len;
+            }
+        } else if self.pos >= end {
+            let over = (self.pos - end) % (2. * len);
+            self.backwards = over < len;
+            self.pos = if self.backwards {
+                end - over
+            } else {
+                start + over - len
+            };
+        } else if self.backwards && self.pos < start {
+            let over = (start - self.pos) % (2. * len);
+            self.backwards = over >= len;
+            self.pos = if self.backwards {
+                end - (over - len)
+            } else {
+                start + over
+            };
+        }
+    }
+
+    /// Interpolated value at the current position
+    fn value(&self, sample: &Sample) -> f32 {
+        let looping = sample.looping;
+        let i = self.pos as usize;
+        let a = sample.data.get(i).copied().unwrap_or(0.);
+        let end = looping.map(|l| l.end).unwrap_or(sample.data.len());
+        let b = match looping {
+            _ if i + 1 < end => sample.data[i + 1],
+            Some(l) if !l.ping_pong => sample.data[l.start],
+            Some(_) => a,
+            None => 0.,
+        };
+        a + (b - a) * self.pos.fract() as f32
+    }
+
+    /// Add the voice to `out`, or only advance its position when there's no output
+    fn mix(&mut self, module: &Module, frames: usize, out: &mut [[f32; 2]]) {
+        let Some(sample) = self.sample.and_then(|s| module.samples.get(s)) else {
+            self.playing = false;
+            return;
+        };
+        // Sample offset may point past the end
+        self.advance(sample, 0.);
+
+        if out.is_empty() {
+            self.advance(sample, self.step * frames as f64);
+            return;
+        }
+        for frame in out {
+            if !self.playing {
+                break;
+            }
+            let value = self.value(sample);
+            frame[0] += value * self.gains[0];
+            frame[1] += value * self.gains[1];
+            self.advance(sample, self.step);
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+struct Channel {
+    voice: Voice,
+    note: Note,
+    instrument: Option<usize>,
+    // Last note played, for instruments without a note
+    key: u8,
+    period: f32,
+    porta_target: f32,
+    volume: i32,
+    pan: f32,
+    // Parameters remembered for effects without one
+    porta_up: u8,
+    porta_down: u8,
+    fine_porta_up: u8,
+    fine_porta_down: u8,
+    extra_fine_porta_up: u8,
+    extra_fine_porta_down: u8,
+    porta_speed: u8,
+    volume_slide: u8,
+    fine_volume_up: u8,
+    fine_volume_down: u8,
+    global_volume_slide: u8,
+    panning_slide: u8,
+    arpeggio: u8,
+    offset: u8,
+    retrigger: u8,
+    tremor: u8,
+    vibrato_speed: u8,
+    vibrato_depth: u8,
+    vibrato_waveform: u8,
+    tremolo_speed: u8,
+    tremolo_depth: u8,
+    tremolo_waveform: u8,
+    // Effect state
+    vibrato_pos: u8,
+    tremolo_pos: u8,
+    tremor_ticks: u8,
+    retrigger_ticks: u8,
+    loop_row: usize,
+    loop_count: u8,
+}
+
+/// Remember a parameter when it's given, and return the remembered one
+fn recall(memory: &mut u8, param: u8) -> u8 {
+    if param > 0 {
+        *memory = param;
+    }
+    *memory
+}
+
+/// Remember each nibble which is given
+fn recall_nibbles(speed: &mut u8, depth: &mut u8, param: u8) {
+    if param >> 4 > 0 {
+        *speed = param >> 4;
+    }
+    if param & 0xf > 0 {
+        *depth = param & 0xf;
+    }
+}
+
+/// Sequencer and mixer state of a playing module
+pub struct Replayer {
+    module: Arc<Module>,
+    channels: Vec<Channel>,
+    speed: u32,
+    tempo: u32,
+    // Global volume from 0 to 64
+    global_volume: i32,
+    pub tick: u32,
+    // Extra rows of ticks to stay on the current row
+    pattern_delay: u32,
+    pub order: usize,
+    pub row: usize,
+    // Row changes requested by effects on the current row
+    jump_order: Option<usize>,
+    break_row: Option<usize>,
+    loop_jump: Option<usize>,
+    visited: Vec<Vec<bool>>,
+    // Frames left in the current tick and fractional frames carried over from previous ticks
+    pub tick_frames: u64,
+    tick_frac: f64,
+    pub ended: bool,
+}
+
+impl Replayer {
+    pub fn new(module: Arc<Module>) -> Self {
+        let channels = (0..module.channels)
+            .map(|_| Channel {
+                pan: 0.5,
+                key: MIDDLE_NOTE as u8,
+                ..Channel::default()
+            })
+            .collect();
+        Self {
+            channels,
+            visited: (0..module.orders.len())
+                .map(|order| vec![false; module.rows(order)])
+                .collect(),
+            speed: module.speed.max(1),
+            tempo: module.tempo.max(1),
+            global_volume: 64,
+            ended: module.orders.is_empty(),
+            module,
+            tick: 0,
+            pattern_delay: 0,
+            order: 0,
+            row: 0,
+            jump_order: None,
+            break_row: None,
+            loop_jump: None,
+            tick_frames: 0,
+            tick_frac: 0.,
+        }
+    }
+
+    /// Play the note of a channel's row, or start a delayed one
+    fn play_note(&mut self, c: usize) {
+        let module = self.module.clone();
+        let ch = &mut self.channels[c];
+        let note = ch.note;
+        let porta = matches!(
+            note.effect,
+            Effect::TonePorta(_) | Effect::TonePortaVolumeSlide(_)
+        ) || matches!(note.volume, Volume::TonePorta(_));
+
+        if note.instrument > 0 && usize::from(note.instrument) <= module.instruments.len() {
+            ch.instrument = Some(usize::from(note.instrument - 1));
+        }
+        if let Key::Note(key) = note.key {
+            ch.key = key;
+        }
+        let instrument = ch.instrument.map(|i| &module.instruments[i]);
+        let mapped = instrument.and_then(|instrument| {
+            let (key, sample) = *instrument.keymap.get(usize::from(ch.key))?;
+            Some((key, sample.filter(|&s| s < module.samples.len())?))
+        });
+
+        if let (true, Some((_, s))) = (note.instrument > 0, mapped) {
+            let sample = &module.samples[s];
+            ch.volume = i32::from(sample.volume);
+            ch.pan = sample.panning;
+            // An instrument without a note restarts envelopes
+            if note.key == Key::None {
+                ch.voice.restart_envelopes();
+            }
+        }
+
+        match note.key {
+            Key::Note(_) => match mapped {
+                Some((key, s)) => {
+                    let period = module
+                        .frequencies
+                        .period(f32::from(key) + module.samples[s].tune);
+                    if porta {
+                        ch.porta_target = period;
+                    } else {
+                        let offset = match note.effect {
+                            Effect::SampleOffset(_) => u32::from(ch.offset) << 8,
+                            _ => 0,
+                        };
+                        ch.period = period;
+                        ch.voice.start(ch.instrument, s, f64::from(offset));
+                        ch.vibrato_pos = 0;
+                        ch.tremolo_pos = 0;
+                        ch.retrigger_ticks = 0;
+                    }
+                }
+                None if !porta => ch.voice.playing = false,
+                None => {}
+            },
+            Key::Off => ch.voice.release(&module),
+            Key::None => {}
+        }
+
+        match note.volume {
+            Volume::Set(volume) => ch.volume = i32::from(volume.min(64)),
+            Volume::FineUp(x) => ch.volume = (ch.volume + i32::from(x)).min(64),
+            Volume::FineDown(x) => ch.volume = (ch.volume - i32::from(x)).max(0),
+            Volume::VibratoSpeed(x) if x > 0 => ch.vibrato_speed = x,
+            Volume::Vibrato(x) if x > 0 => ch.vibrato_depth = x,
+            Volume::Panning(pan) => ch.pan = f32::from(pan) / 255.,
+            Volume::TonePorta(speed) if speed > 0 => ch.porta_speed = speed,
+            _ => {}
+        }
+    }
+
+    /// Fill in remembered parameters of an effect
+    fn recall_effect(&mut self, c: usize, effect: Effect) -> Effect {
+        let ch = &mut self.channels[c];
+        match effect {
+            Effect::Arpeggio(p) => Effect::Arpeggio(recall(&mut ch.arpeggio, p)),
+            Effect::PortaUp(p) => Effect::PortaUp(recall(&mut ch.porta_up, p)),
+            Effect::PortaDown(p) => Effect::PortaDown(recall(&mut ch.porta_down, p)),
+            Effect::FinePortaUp(p) => Effect::FinePortaUp(recall(&mut ch.fine_porta_up, p)),
+            Effect::FinePortaDown(p) => Effect::FinePortaDown(recall(&mut ch.fine_porta_down, p)),
+            Effect::ExtraFinePortaUp(p) => {
+                Effect::ExtraFinePortaUp(recall(&mut ch.extra_fine_porta_up, p))
+            }
+            Effect::ExtraFinePortaDown(p) => {
+                Effect::ExtraFinePortaDown(recall(&mut ch.extra_fine_porta_down, p))
+            }
+            Effect::TonePorta(p) => Effect::TonePorta(recall(&mut ch.porta_speed, p)),
+            Effect::Vibrato(p) => {
+                recall_nibbles(&mut ch.vibrato_speed, &mut ch.vibrato_depth, p);
+                effect
+            }
+            Effect::Tremolo(p) => {
+                recall_nibbles(&mut ch.tremolo_speed, &mut ch.tremolo_depth, p);
+                effect
+            }
+            Effect::TonePortaVolumeSlide(p) => {
+                Effect::TonePortaVolumeSlide(recall(&mut ch.volume_slide, p))
+            }
+            Effect::VibratoVolumeSlide(p) => {
+                Effect::VibratoVolumeSlide(recall(&mut ch.volume_slide, p))
+            }
+            Effect::VolumeSlide(p) => Effect::VolumeSlide(recall(&mut ch.volume_slide, p)),
+            Effect::FineVolumeUp(p) => Effect::FineVolumeUp(recall(&mut ch.fine_volume_up, p)),
+            Effect::FineVolumeDown(p) => {
+                Effect::FineVolumeDown(recall(&mut ch.fine_volume_down, p))
+            }
+            Effect::Tremor(p) => Effect::Tremor(recall(&mut ch.tremor, p)),
+            Effect::SampleOffset(p) => Effect::SampleOffset(recall(&mut ch.offset, p)),
+            Effect::GlobalVolumeSlide(p) => {
+                Effect::GlobalVolumeSlide(recall(&mut ch.global_volume_slide, p))
+            }
+            Effect::PanningSlide(p) => Effect::PanningSlide(recall(&mut ch.panning_slide, p)),
+            Effect::MultiRetrigger(p) => Effect::MultiRetrigger(recall(&mut ch.retrigger, p)),
+            _ => effect,
+        }
+    }
+
+    /// Read the notes of the current row and apply first tick effects
+    fn start_row(&mut self) {
+        self.visited[self.order][self.row] = true;
+        let module = self.module.clone();
+        for c in 0..self.channels.len() {
+            let mut note = module.note(self.order, self.row, c);
+            note.effect = self.recall_effect(c, note.effect);
+            self.channels[c].note = note;
+            if !matches!(note.effect, Effect::NoteDelay(delay) if delay > 0) {
+                self.play_note(c);
+            }
+
+            let frequencies = module.frequencies;
+            let ch = &mut self.channels[c];
+            let slide_period = |ch: &mut Channel, amount: i32| {
+                ch.period = frequencies.clamp(ch.period + amount as f32);
+            };
+            match note.effect {
+                Effect::FinePortaUp(p) => slide_period(ch, -i32::from(p) * 4),
+                Effect::FinePortaDown(p) => slide_period(ch, i32::from(p) * 4),
+                Effect::ExtraFinePortaUp(p) => slide_period(ch, -i32::from(p)),
+                Effect::ExtraFinePortaDown(p) => slide_period(ch, i32::from(p)),
+                Effect::Panning(pan) => ch.pan = f32::from(pan) / 255.,
+                Effect::SetVolume(volume) => ch.volume = i32::from(volume.min(64)),
+                Effect::FineVolumeUp(p) => ch.volume = (ch.volume + i32::from(p)).min(64),
+                Effect::FineVolumeDown(p) => ch.volume = (ch.volume - i32::from(p)).max(0),
+                Effect::GlobalVolume(volume) => self.global_volume = i32::from(volume.min(64)),
+                Effect::VibratoWaveform(waveform) => ch.vibrato_waveform = waveform,
+                Effect::TremoloWaveform(waveform) => ch.tremolo_waveform = waveform,
+                Effect::SetEnvelopePosition(pos) => {
+                    ch.voice.volume_envelope = u32::from(pos);
+                    ch.voice.panning_envelope = u32::from(pos);
+                }
+                Effect::PositionJump(order) => self.jump_order = Some(usize::from(order)),
+                Effect::PatternBreak(row) => self.break_row = Some(usize::from(row)),
+                Effect::PatternLoop(0) => ch.loop_row = self.row,
+                Effect::PatternLoop(count) => {
+                    if ch.loop_count == 0 {
+                        ch.loop_count = count;
+                        self.loop_jump = Some(ch.loop_row);
+                    } else {
+                        ch.loop_count -= 1;
+                        if ch.loop_count > 0 {
+                            self.loop_jump = Some(ch.loop_row);
+                        }
+                    }
+                }
+                Effect::PatternDelay(rows) if self.pattern_delay == 0 => {
+                    self.pattern_delay = u32::from(rows)
+                }
+                Effect::SetSpeed(speed) if speed > 0 => self.speed = u32::from(speed),
+                Effect::SetTempo(tempo) if tempo >= 0x20 => self.tempo = u32::from(tempo),
+                _ => {}
+            }
+        }
+    }
+
+    /// Apply effects which happen on a given tick of the row, including the first
+    fn timed_effects(&mut self, tick: u32) {
+        let module = self.module.clone();
+        for c in 0..self.channels.len() {
+            let effect = self.channels[c].note.effect;
+            let ch = &mut self.channels[c];
+            match effect {
+                Effect::NoteCut(at) if tick == u32::from(at) => ch.volume = 0,
+                Effect::KeyOff(at) if tick == u32::from(at) => ch.voice.release(&module),
+                Effect::NoteDelay(at) if at > 0 && tick == u32::from(at) => self.play_note(c),
+                Effect::Retrigger(every)
+                    if tick > 0 && tick.checked_rem(u32::from(every)) == Some(0) =>
+                {
+                    ch.voice.pos = 0.;
+                }
+                Effect::MultiRetrigger(p) => {
+                    ch.retrigger_ticks += 1;
+                    if ch.retrigger_ticks >= (p & 0xf).max(1) {
+                        ch.retrigger_ticks = 0;
+                        ch.voice.pos = 0.;
+                        ch.volume = match p >> 4 {
+                            0x1..=0x5 => ch.volume - (1 << ((p >> 4) - 1)),
+                            0x6 => ch.volume * 2 / 3,
+                            0x7 => ch.volume / 2,
+                            0x9..=0xd => ch.volume + (1 << ((p >> 4) - 9)),
+                            0xe => ch.volume * 3 / 2,
+                            0xf => ch.volume * 2,
+                            _ => ch.volume,
+                        }
+                        .clamp(0, 64);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Apply effects which run on every tick but the first of a row
+    fn update_effects(&mut self) {
+        let frequencies = self.module.frequencies;
+        for c in 0..self.channels.len() {
+            let ch = &mut self.channels[c];
+            let note = ch.note;
+
+            let slide_period = |ch: &mut Channel, amount: i32| {
+                ch.period = frequencies.clamp(ch.period + amount as f32);
+            };
+            let slide_volume = |ch: &mut Channel, amount: i32| {
+                ch.volume = (ch.volume + amount).clamp(0, 64);
+            };
+            let volume_slide = |ch: &mut Channel, p: u8| {
+                slide_volume(ch, i32::from(p >> 4) - i32::from(p & 0xf));
+            };
+            let tone_porta = |ch: &mut Channel| {
+                let speed = f32::from(ch.porta_speed) * 4.;
+                if ch.period < ch.porta_target {
+                    ch.period = (ch.period + speed).min(ch.porta_target);
+                } else {
+                    ch.period = (ch.period - speed).max(ch.porta_target);
+                }
+            };
+
+            match note.volume {
+                Volume::SlideUp(x) => slide_volume(ch, i32::from(x)),
+                Volume::SlideDown(x) => slide_volume(ch, -i32::from(x)),
+                Volume::PanningSlideLeft(x) => {
+                    ch.pan = (ch.pan - f32::from(x) / 255.).clamp(0., 1.)
+                }
+                Volume::PanningSlideRight(x) => {
+                    ch.pan = (ch.pan + f32::from(x) / 255.).clamp(0., 1.)
+                }
+                Volume::TonePorta(_) => tone_porta(ch),
+                Volume::Vibrato(_) => {
+                    ch.vibrato_pos = (ch.vibrato_pos + ch.vibrato_speed) % 64;
+                }
+                _ => {}
+            }
+
+            match note.effect {
+                Effect::PortaUp(p) => slide_period(ch, -i32::from(p) * 4),
+                Effect::PortaDown(p) => slide_period(ch, i32::from(p) * 4),
+                Effect::TonePorta(_) => tone_porta(ch),
+                Effect::Vibrato(_) => {
+                    ch.vibrato_pos = (ch.vibrato_pos + ch.vibrato_speed) % 64;
+                }
+                Effect::VibratoVolumeSlide(p) => {
+                    ch.vibrato_pos = (ch.vibrato_pos + ch.vibrato_speed) % 64;
+                    volume_slide(ch, p);
+                }
+                Effect::TonePortaVolumeSlide(p) => {
+                    tone_porta(ch);
+                    volume_slide(ch, p);
+                }
+                Effect::VolumeSlide(p) => volume_slide(ch, p),
+                Effect::Tremolo(_) => ch.tremolo_pos = (ch.tremolo_pos + ch.tremolo_speed) % 64,
+                Effect::PanningSlide(p) => {
+                    let amount = i32::from(p >> 4) - i32::from(p & 0xf);
+                    ch.pan = (ch.pan + amount as f32 / 255.).clamp(0., 1.);
+                }
+                Effect::GlobalVolumeSlide(p) => {
+                    let amount = i32::from(p >> 4) - i32::from(p & 0xf);
+                    self.global_volume = (self.global_volume + amount).clamp(0, 64);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Compute what is heard during this tick
+    fn update_output(&mut self) {
+        let module = self.module.clone();
+        let tick = self.tick % self.speed;
+        let global_volume = self.global_volume as f32 / 64.;
+        for ch in &mut self.channels {
+            let note = ch.note;
+            let mut period = ch.period;
+            let mut volume = ch.volume;
+            let vibrato = matches!(
+                note.effect,
+                Effect::Vibrato(_) | Effect::VibratoVolumeSlide(_)
+            ) || matches!(note.volume, Volume::Vibrato(_));
+
+            match note.effect {
+                Effect::Arpeggio(p) => {
+                    let semitones = match tick % 3 {
+                        0 => 0,
+                        1 => p >> 4,
+                        _ => p & 0xf,
+                    };
+                    period = module.frequencies.transpose(period, f32::from(semitones));
+                }
+                Effect::Tremolo(_) if tick > 0 => {
+                    let delta = waveform(ch.tremolo_waveform, ch.tremolo_pos)
+                        * i32::from(ch.tremolo_depth)
+                        / 64;
+                    volume = (volume + delta).clamp(0, 64);
+                }
+                Effect::Tremor(p) => {
+                    let (on, off) = ((p >> 4) + 1, (p & 0xf) + 1);
+                    if ch.tremor_ticks % (on + off) >= on {
+                        volume = 0;
+                    }
+                    ch.tremor_ticks = (ch.tremor_ticks + 1) % (on + off);
+                }
+                _ => {}
+            }
+            if vibrato && tick > 0 {
+                // Depth is in Amiga periods, which are 4 period units
+                let delta = waveform(ch.vibrato_waveform, ch.vibrato_pos)
+                    * i32::from(ch.vibrato_depth)
+                    / 128;
+                period += (delta * 4) as f32;
+            }
+
+            ch.voice.period = period;
+            ch.voice.volume = volume as f32 / 64.;
+            ch.voice.pan = ch.pan;
+            ch.voice.update(&module, global_volume);
+        }
+    }
+
+    /// Move to the row after the current one
+    fn next_row(&mut self) {
+        let jumped = self.jump_order.is_some() || self.break_row.is_some();
+        let (order, row) = if let Some(row) = self.loop_jump.take() {
+            (self.order, row)
+        } else if jumped {
+            (
+                self.jump_order.unwrap_or(self.order + 1),
+                self.break_row.unwrap_or(0),
+            )
+        } else if self.row + 1 < self.module.rows(self.order) {
+            (self.order, self.row + 1)
+        } else {
+            (self.order + 1, 0)
+        };
+        let left_pattern = jumped || order != self.order;
+        self.jump_order = None;
+        self.break_row = None;
+
+        // The song ends after the last order or when it jumps back to where it has been.
+        // Pattern loops revisit rows within the pattern, so those don't count.
+        if order >= self.module.orders.len() {
+            self.ended = true;
+            return;
+        }
+        let row = row.min(self.module.rows(order) - 1);
+        if left_pattern && self.visited[order][row] {
+            self.ended = true;
+        }
+        self.order = order;
+        self.row = row;
+    }
+
+    /// Run the sequencer for the next tick
+    ///
+    /// # Return value
+    ///
+    /// Returns true if a new row started
+    pub fn next_tick(&mut self) -> bool {
+        let row_started = self.tick == 0;
+        if row_started {
+            self.start_row();
+        } else {
+            self.update_effects();
+        }
+        self.timed_effects(self.tick % self.speed);
+        self.update_output();
+
+        let tick_len = f64::from(SAMPLE_RATE) * 2.5 / f64::from(self.tempo) + self.tick_frac;
+        self.tick_frames = tick_len as u64;
+        self.tick_frac = tick_len.fract();
+
+        self.tick += 1;
+        if self.tick >= self.speed * (1 + self.pattern_delay) {
+            self.tick = 0;
+            self.pattern_delay = 0;
+            self.next_row();
+        }
+        row_started
+    }
+
+    /// Play `frames` frames of the current tick, mixing to `out` when given
+    pub fn mix(&mut self, frames: u64, mut out: Option<&mut Vec<i16>>) {
+        let start = out.as_ref().map(|out| out.len()).unwrap_or(0);
+        if let Some(out) = &mut out {
+            out.resize(start + frames as usize * 2, 0);
+        }
+        let mut mixed = vec![[0f32; 2]; if out.is_some() { frames as usize } else { 0 }];
+
+        let module = &self.module;
+        let voices = self.channels.iter_mut().map(|ch| &mut ch.voice);
+        for voice in voices.filter(|voice| voice.playing) {
+            voice.mix(module, frames as usize, &mut mixed);
+        }
+
+        if let Some(out) = out {
+            for (i, frame) in mixed.iter().enumerate() {
+                for (j, value) in frame.iter().enumerate() {
+                    out[start + i * 2 + j] =
+                        (value * i16::MAX as f32).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+                }
+            }
+        }
+    }
+}
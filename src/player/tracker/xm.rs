@@ -0,0 +1,444 @@
+use super::module::*;
+use anyhow::{anyhow, Result};
+
+const MAGIC: &[u8] = b"Extended Module: ";
+// FastTracker 2 plays Amiga period n at this / n Hz
+const AMIGA_CLOCK: f64 = 8363. * 1712.;
+const KEY_OFF: u8 = 97;
+const MAX_ENVELOPE_POINTS: usize = 12;
+// Reserved byte of sample headers marking ModPlug's 4-bit ADPCM
+const ADPCM: u8 = 0xad;
+
+/// Parse a FastTracker 2 extended module
+pub fn parse(data: &[u8]) -> Result<Module> {
+    let r = Reader { data };
+    if !data.starts_with(MAGIC) {
+        return Err(anyhow!("Not an XM file"));
+    }
+
+    let header_size = r.u32(60)? as usize;
+    let song_len = usize::from(r.u16(64)?).min(256);
+    let channels = usize::from(r.u16(68)?);
+    let pattern_count = usize::from(r.u16(70)?);
+    let instrument_count = usize::from(r.u16(72)?);
+    let linear = r.u16(74)? & 1 != 0;
+    if channels == 0 || channels > 64 {
+        return Err(anyhow!("XM file has {} channels", channels));
+    }
+    let orders = r.bytes(80, song_len)?.to_vec();
+
+    let mut offset = 60 + header_size;
+    let mut patterns = Vec::with_capacity(pattern_count);
+    for _ in 0..pattern_count {
+        let pattern_header = r.u32(offset)? as usize;
+        let rows = usize::from(r.u16(offset + 5)?).max(1);
+        let packed_len = usize::from(r.u16(offset + 7)?);
+        offset += pattern_header;
+        patterns.push(Pattern {
+            rows,
+            notes: unpack(r.bytes(offset, packed_len)?, rows * channels),
+        });
+        offset += packed_len;
+    }
+
+    let mut instruments = Vec::with_capacity(instrument_count);
+    let mut samples = Vec::new();
+    for _ in 0..instrument_count {
+        let instrument_size = r.u32(offset)? as usize;
+        let sample_count = usize::from(r.u16(offset + 27)?);
+        if sample_count == 0 {
+            instruments.push(Instrument {
+                keymap: vec![(0, None); NOTE_COUNT],
+                volume_envelope: None,
+                panning_envelope: None,
+                fadeout: 0,
+            });
+            offset += instrument_size;
+            continue;
+        }
+
+        let (instrument, vibrato) = instrument(&r, offset, samples.len(), sample_count)?;
+        instruments.push(instrument);
+        let sample_header_size = r.u32(offset + 29)? as usize;
+        offset += instrument_size;
+
+        let mut headers = Vec::with_capacity(sample_count);
+        for _ in 0..sample_count {
+            headers.push(r.bytes(offset, 40)?);
+            offset += sample_header_size;
+        }
+        for header in headers {
+            let (sample, len) = sample(header, r.up_to(offset, usize::MAX), vibrato);
+            samples.push(sample);
+            offset += len;
+        }
+    }
+
+    Ok(Module {
+        channels,
+        instruments,
+        samples,
+        orders,
+        patterns,
+        frequencies: if linear {
+            Frequencies::Linear
+        } else {
+            Frequencies::Amiga {
+                clock: AMIGA_CLOCK,
+                min: 1.,
+                max: 32000.,
+            }
+        },
+        speed: u32::from(r.u16(76)?),
+        tempo: u32::from(r.u16(78)?),
+        gain: Module::default_gain(channels),
+    })
+}
+
+/// Decode packed pattern data, an empty pattern has no data
+fn unpack(packed: &[u8], len: usize) -> Vec<Note> {
+    let mut bytes = packed.iter().copied();
+    let mut notes = Vec::with_capacity(len);
+    for _ in 0..len {
+        let mut next = || bytes.next().unwrap_or(0);
+        let first = next();
+        let mut cell = [0; 5];
+        if first & 0x80 != 0 {
+            for (i, value) in cell.iter_mut().enumerate() {
+                if first & (1 << i) != 0 {
+                    *value = next();
+                }
+            }
+        } else {
+            cell[0] = first;
+            for value in &mut cell[1..] {
+                *value = next();
+            }
+        }
+        notes.push(note(cell));
+    }
+    notes
+}
+
+fn note([key, instrument, volume, effect, param]: [u8; 5]) -> Note {
+    let (x, y) = (param >> 4, param & 0xf);
+    Note {
+        key: match key {
+            1..=96 => Key::Note(key + 11),
+            KEY_OFF => Key::Off,
+            _ => Key::None,
+        },
+        instrument,
+        volume: match volume {
+            0x10..=0x50 => Volume::Set(volume - 0x10),
+            0x60..=0x6f => Volume::SlideDown(volume & 0xf),
+            0x70..=0x7f => Volume::SlideUp(volume & 0xf),
+            0x80..=0x8f => Volume::FineDown(volume & 0xf),
+            0x90..=0x9f => Volume::FineUp(volume & 0xf),
+            0xa0..=0xaf => Volume::VibratoSpeed(volume & 0xf),
+            0xb0..=0xbf => Volume::Vibrato(volume & 0xf),
+            0xc0..=0xcf => Volume::Panning((volume & 0xf) * 17),
+            0xd0..=0xdf => Volume::PanningSlideLeft(volume & 0xf),
+            0xe0..=0xef => Volume::PanningSlideRight(volume & 0xf),
+            0xf0..=0xff => Volume::TonePorta((volume & 0xf) << 4),
+            _ => Volume::None,
+        },
+        effect: match effect {
+            0x0 if param > 0 => Effect::Arpeggio(param),
+            0x1 => Effect::PortaUp(param),
+            0x2 => Effect::PortaDown(param),
+            0x3 => Effect::TonePorta(param),
+            0x4 => Effect::Vibrato(param),
+            0x5 => Effect::TonePortaVolumeSlide(param),
+            0x6 => Effect::VibratoVolumeSlide(param),
+            0x7 => Effect::Tremolo(param),
+            0x8 => Effect::Panning(param),
+            0x9 => Effect::SampleOffset(param),
+            0xa => Effect::VolumeSlide(param),
+            0xb => Effect::PositionJump(param),
+            0xc => Effect::SetVolume(param),
+            0xd => Effect::PatternBreak(x * 10 + y),
+            0xe => match x {
+                0x1 => Effect::FinePortaUp(y),
+                0x2 => Effect::FinePortaDown(y),
+                0x4 => Effect::VibratoWaveform(y),
+                0x6 => Effect::PatternLoop(y),
+                0x7 => Effect::TremoloWaveform(y),
+                0x8 => Effect::Panning(y * 17),
+                0x9 => Effect::Retrigger(y),
+                0xa => Effect::FineVolumeUp(y),
+                0xb => Effect::FineVolumeDown(y),
+                0xc => Effect::NoteCut(y),
+                0xd => Effect::NoteDelay(y),
+                0xe => Effect::PatternDelay(y),
+                _ => Effect::None,
+            },
+            0xf if param > 0 && param < 32 => Effect::SetSpeed(param),
+            0xf if param >= 32 => Effect::SetTempo(param),
+            // G, H, K, L, P, R, T and X
+            16 => Effect::GlobalVolume(param),
+            17 => Effect::GlobalVolumeSlide(param),
+            20 => Effect::KeyOff(param),
+            21 => Effect::SetEnvelopePosition(param),
+            25 => Effect::PanningSlide(param),
+            27 => Effect::MultiRetrigger(param),
+            29 => Effect::Tremor(param),
+            33 if x == 1 => Effect::ExtraFinePortaUp(y),
+            33 if x == 2 => Effect::ExtraFinePortaDown(y),
+            _ => Effect::None,
+        },
+    }
+}
+
+/// Read an instrument with samples, and the vibrato which its samples use
+fn instrument(
+    r: &Reader,
+    offset: usize,
+    first_sample: usize,
+    sample_count: usize,
+) -> Result<(Instrument, AutoVibrato)> {
+    let keys = r.bytes(offset + 33, 96)?;
+    let keymap = (0..NOTE_COUNT as u8)
+        .map(|note| {
+            let key = usize::from(note.saturating_sub(12)).min(95);
+            let sample = usize::from(keys[key]);
+            (
+                note,
+                (sample < sample_count).then_some(first_sample + sample),
+            )
+        })
+        .collect();
+
+    // Points, then counts, sustain points and loops of the volume envelope followed by the
+    // panning envelope's, then the flags of each
+    let envelope = |points_at, count_at, sustain_at, flags_at, pan: bool| {
+        let count = usize::from(r.u8(offset + count_at)?).min(MAX_ENVELOPE_POINTS);
+        let mut points: Vec<(u32, f32)> = Vec::with_capacity(count);
+        for i in 0..count {
+            let tick = u32::from(r.u16(offset + points_at + i * 4)?);
+            let value = f32::from(r.u16(offset + points_at + i * 4 + 2)?.min(64));
+            let value = if pan {
+                (value - 32.) / 32.
+            } else {
+                value / 64.
+            };
+            // Ticks must increase, broken files get the point moved
+            let tick = points.last().map_or(0, |&(last, _)| tick.max(last + 1));
+            points.push((tick, value));
+        }
+        let flags = r.u8(offset + flags_at)?;
+        let sustain = usize::from(r.u8(offset + sustain_at)?);
+        let looping = (
+            usize::from(r.u8(offset + sustain_at + 1)?),
+            usize::from(r.u8(offset + sustain_at + 2)?),
+        );
+        anyhow::Ok(Envelope::new(
+            flags & 1 != 0,
+            points,
+            (flags & 4 != 0).then_some(looping),
+            (flags & 2 != 0).then_some((sustain, sustain)),
+        ))
+    };
+    let volume_envelope = envelope(129, 225, 227, 233, false)?;
+    let panning_envelope = envelope(177, 226, 230, 234, true)?;
+
+    let vibrato = AutoVibrato {
+        waveform: match r.u8(offset + 235)? {
+            1 => Waveform::Square,
+            2 => Waveform::RampDown,
+            3 => Waveform::RampUp,
+            _ => Waveform::Sine,
+        },
+        sweep: u32::from(r.u8(offset + 236)?),
+        depth: f32::from(r.u8(offset + 237)?),
+        rate: r.u8(offset + 238)?,
+    };
+
+    let instrument = Instrument {
+        keymap,
+        volume_envelope,
+        panning_envelope,
+        // FastTracker fades from 32768
+        fadeout: u32::from(r.u16(offset + 239)?) * 2,
+    };
+    Ok((instrument, vibrato))
+}
+
+/// Read a sample from its header and data, and the length of its data in the file
+fn sample(header: &[u8], data: &[u8], vibrato: AutoVibrato) -> (Sample, usize) {
+    let u32_at = |at: usize| u32::from_le_bytes(header[at..at + 4].try_into().unwrap()) as usize;
+    let (len, loop_start, loop_len) = (u32_at(0), u32_at(4), u32_at(8));
+    let (volume, finetune, flags, pan, relative_note) = (
+        header[12],
+        header[13] as i8,
+        header[14],
+        header[15],
+        header[16] as i8,
+    );
+    let bits16 = flags & 0x10 != 0;
+
+    let (decoded, stored_len) = if header[17] == ADPCM {
+        let stored_len = 16 + len.div_ceil(2);
+        (decode_adpcm(data, len), stored_len)
+    } else if bits16 {
+        let mut value = 0i16;
+        let decoded = data[..len.min(data.len())]
+            .chunks_exact(2)
+            .map(|b| {
+                value = value.wrapping_add(i16::from_le_bytes([b[0], b[1]]));
+                f32::from(value) / 32768.
+            })
+            .collect();
+        (decoded, len)
+    } else {
+        let mut value = 0i8;
+        let decoded = data[..len.min(data.len())]
+            .iter()
+            .map(|&b| {
+                value = value.wrapping_add(b as i8);
+                f32::from(value) / 128.
+            })
+            .collect();
+        (decoded, len)
+    };
+
+    let unit = if bits16 { 2 } else { 1 };
+    let mut sample = Sample::new(
+        decoded,
+        volume,
+        f32::from(relative_note) + f32::from(finetune) / 128.,
+    );
+    sample.looping = match flags & 3 {
+        1 | 2 => Loop::new(
+            loop_start / unit,
+            (loop_start + loop_len) / unit,
+            sample.data.len(),
+            flags & 3 == 2,
+        ),
+        _ => None,
+    };
+    sample.panning = f32::from(pan) / 255.;
+    sample.vibrato = vibrato;
+    (sample, stored_len)
+}
+
+/// Decode ModPlug ADPCM, which has a table of 16 deltas followed by 4-bit indices to it
+fn decode_adpcm(data: &[u8], len: usize) -> Vec<f32> {
+    let Some((table, indices)) = data.split_at_checked(16) else {
+        return Vec::new();
+    };
+    let mut value = 0i8;
+    indices
+        .iter()
+        .flat_map(|&b| [b & 0xf, b >> 4])
+        .take(len)
+        .map(|index| {
+            value = value.wrapping_add(table[usize::from(index)] as i8);
+            f32::from(value) / 128.
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    const PATTERN: usize = 336;
+    const INSTRUMENT_SIZE: usize = 263;
+    const CHANNELS: usize = 4;
+
+    /// Module with one pattern of `(row, channel, [note, instrument, volume, effect, param])`
+    /// cells and an instrument with a 64 byte looped sample, at speed 6 and tempo 125
+    pub fn module(cells: &[(usize, usize, [u8; 5])]) -> Vec<u8> {
+        let mut data = vec![0; PATTERN];
+        data[..MAGIC.len()].copy_from_slice(MAGIC);
+        data[37] = 0x1a;
+        data[58..60].copy_from_slice(&0x104u16.to_le_bytes());
+        data[60..64].copy_from_slice(&276u32.to_le_bytes());
+        for (at, value) in [
+            (64, 1),
+            (68, CHANNELS as u16),
+            (70, 1),
+            (72, 1),
+            (74, 1),
+            (76, 6),
+            (78, 125),
+        ] {
+            data[at..at + 2].copy_from_slice(&u16::to_le_bytes(value));
+        }
+
+        // Given cells unpacked and the rest packed as empty
+        let mut packed = Vec::new();
+        for i in 0..64 * CHANNELS {
+            match cells.iter().find(|cell| cell.0 * CHANNELS + cell.1 == i) {
+                Some((_, _, cell)) => packed.extend(cell),
+                None => packed.push(0x80),
+            }
+        }
+        data.extend(9u32.to_le_bytes());
+        data.extend([0, 64, 0]);
+        data.extend((packed.len() as u16).to_le_bytes());
+        data.extend(&packed);
+
+        let instrument = data.len();
+        data.resize(instrument + INSTRUMENT_SIZE, 0);
+        data[instrument..instrument + 4].copy_from_slice(&(INSTRUMENT_SIZE as u32).to_le_bytes());
+        data[instrument + 27] = 1;
+        data[instrument + 29..instrument + 33].copy_from_slice(&40u32.to_le_bytes());
+
+        let mut header = [0; 40];
+        header[..4].copy_from_slice(&64u32.to_le_bytes());
+        header[8..12].copy_from_slice(&64u32.to_le_bytes());
+        header[12] = 64;
+        header[14] = 1;
+        header[15] = 128;
+        data.extend(header);
+
+        // Sample data is stored as deltas
+        let mut last = 0i8;
+        data.extend((0..64).map(|i| {
+            let value = ((i as f32 / 32. * std::f32::consts::PI).sin() * 100.) as i8;
+            let delta = value.wrapping_sub(last);
+            last = value;
+            delta as u8
+        }));
+        data
+    }
+
+    #[test]
+    fn parses_module() {
+        let module = parse(&module(&[(0, 0, [49, 1, 0x20, 0xf, 3])])).unwrap();
+        assert_eq!(module.channels, CHANNELS);
+        assert_eq!(module.orders, [0]);
+        assert_eq!((module.speed, module.tempo), (6, 125));
+        assert!(matches!(module.frequencies, Frequencies::Linear));
+        assert_eq!(module.patterns[0].rows, 64);
+        assert_eq!(module.instruments.len(), 1);
+        assert_eq!(module.instruments[0].keymap[60], (60, Some(0)));
+        assert_eq!(module.samples.len(), 1);
+        assert_eq!(module.samples[0].data.len(), 64);
+        assert!(module.samples[0].data[16] > 0.7);
+        assert!(module.samples[0].looping == Loop::new(0, 64, 64, false));
+
+        let note = module.note(0, 0, 0);
+        assert_eq!(note.key, Key::Note(MIDDLE_NOTE as u8));
+        assert_eq!(note.instrument, 1);
+        assert_eq!(note.volume, Volume::Set(16));
+        assert_eq!(note.effect, Effect::SetSpeed(3));
+        assert_eq!(module.note(0, 0, 1), Note::default());
+        assert_eq!(module.note(0, 63, 3), Note::default());
+    }
+
+    #[test]
+    fn rejects_truncated_file() {
+        let data = module(&[]);
+        // Sample data may be cut short, anything before it can't
+        let sample_data = data.len() - 64;
+        for len in 0..sample_data {
+            assert!(parse(&data[..len]).is_err(), "{} bytes", len);
+        }
+        for len in sample_data..data.len() {
+            assert!(parse(&data[..len]).is_ok(), "{} bytes", len);
+        }
+    }
+}
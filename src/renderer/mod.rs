@@ -74,7 +74,8 @@ pub struct PostUniforms {
     bloom_offset: Vec2,
     bloom_sample_bias: f32,
     bloom_multiplier: f32,
-    _pad: Vec2,
+    flash: f32,
+    _pad: f32,
 }
 
 #[repr(C)]
@@ -921,7 +922,8 @@ impl Renderer {
             bloom_offset: vec2(1., 0.),
             bloom_sample_bias: scene.bloom_floor,
             bloom_multiplier: scene.bloom_amount,
-            _pad: Vec2::ZERO,
+            flash: scene.flash,
+            _pad: 0.,
         };
 
         self.queue.write_buffer(
@@ -123,6 +123,10 @@ pub struct Scene {
     pub bloom_amount: f32,
     pub march_multiplier: f32,
     pub world_triangles: f32,
+    /// White flash over the final image, from 0 to 1
+    pub flash: f32,
+    /// Beat strength which drives the renderer's effects
+    pub beat: f32,
     pub lights: Vec<Light>,
     pub camera: Camera,
 }
@@ -0,0 +1,54 @@
+use super::load_tracks;
+use anyhow::Result;
+use rust_rocket::track::Track;
+use std::{collections::HashSet, fmt};
+
+/// Sync tracks which the demo reads but are missing, and tracks which it never reads
+pub struct TrackReport {
+    pub missing: Vec<String>,
+    pub unused: Vec<String>,
+}
+
+impl TrackReport {
+    pub fn new(tracks: &[Track], required: &[String]) -> Self {
+        let present: HashSet<&str> = tracks.iter().map(Track::get_name).collect();
+        let required_set: HashSet<&str> = required.iter().map(String::as_str).collect();
+        Self {
+            missing: required
+                .iter()
+                .filter(|name| !present.contains(name.as_str()))
+                .cloned()
+                .collect(),
+            unused: tracks
+                .iter()
+                .map(Track::get_name)
+                .filter(|name| !required_set.contains(name))
+                .map(str::to_owned)
+                .collect(),
+        }
+    }
+
+    /// True when no required track is missing
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty()
+    }
+}
+
+impl fmt::Display for TrackReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (title, names) in [("Missing", &self.missing), ("Unused", &self.unused)] {
+            if !names.is_empty() {
+                writeln!(f, "{} sync tracks:", title)?;
+                for name in names {
+                    writeln!(f, "    {}", name)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compare the tracks which release builds play to the `required` track names
+pub fn check_tracks(required: &[String]) -> Result<TrackReport> {
+    Ok(TrackReport::new(&load_tracks()?, required))
+}
@@ -1,9 +1,12 @@
+use crate::Player;
 use std::time::{Duration, Instant};
 
 pub struct FrameCounter {
     frames: u32,
     last_frame: Instant,
     max_frametime: Option<Duration>,
+    // Largest audio clock drift in seconds during the interval
+    max_drift: Option<f32>,
     since: Instant,
     interval: Duration,
 }
@@ -15,6 +18,7 @@ impl FrameCounter {
             frames: 0,
             last_frame: time,
             max_frametime: None,
+            max_drift: None,
             since: time,
             interval,
         }
@@ -24,8 +28,12 @@ impl FrameCounter {
         Self::with_interval(Duration::new(1, 0))
     }
 
-    pub fn tick(&mut self) {
+    /// Call once per frame
+    pub fn tick(&mut self, player: &Player) {
         self.frames += 1;
+        if let Some(drift) = player.drift_secs() {
+            self.max_drift = Some(self.max_drift.unwrap_or(0.).max(drift.abs()));
+        }
 
         let frametime = self.last_frame.elapsed();
         self.last_frame = Instant::now();
@@ -46,10 +54,25 @@ impl FrameCounter {
                     (elapsed.as_secs_f64() / self.frames as f64) * 1000.,
                     max_frametime.as_secs_f64() * 1000.,
                 );
+                if let Some(max_drift) = self.max_drift {
+                    log::info!("Audio clock drift max {:.2}ms", max_drift * 1000.);
+                }
+                let stats = player.audio_stats();
+                if stats.callbacks > 0 {
+                    log::info!(
+                        "Audio {} underruns, {} late buffers, {} errors in total. Callback avg {:.2}ms, max {:.2}ms",
+                        stats.underruns,
+                        stats.late_buffers,
+                        stats.errors,
+                        stats.avg_callback().as_secs_f64() * 1000.,
+                        stats.max_callback.as_secs_f64() * 1000.,
+                    );
+                }
 
                 // Reset counting fields
                 self.frames = 0;
                 self.max_frametime = None;
+                self.max_drift = None;
             }
         } else {
             self.max_frametime = Some(frametime);
@@ -0,0 +1,365 @@
+use super::track_file::{KeyData, TrackData};
+use anyhow::{anyhow, Context, Result};
+use rust_rocket::{interpolation::Interpolation, track::Track};
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        mpsc::{self, Receiver, Sender, TryRecvError},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+const CLIENT_GREETING: &[u8] = b"hello, synctracker!";
+const SERVER_GREETING: &[u8] = b"hello, demo!";
+// Editor to demo messages
+const SET_KEY: u8 = 0;
+const DELETE_KEY: u8 = 1;
+const SET_ROW: u8 = 3;
+const PAUSE: u8 = 4;
+const SAVE_TRACKS: u8 = 5;
+// Demo to editor messages
+const GET_TRACK: u8 = 2;
+// How often the server thread polls for connections, messages and commands
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+enum Command {
+    SetRow(u32),
+    Pause(bool),
+    SaveTracks,
+    SetKey(String, KeyData),
+    DeleteKey(String, u32),
+    Disconnect,
+}
+
+#[derive(Default)]
+struct Status {
+    connected: bool,
+    connections: usize,
+    row: Option<u32>,
+    requested: Vec<String>,
+}
+
+/// In-process stand-in for the GNU Rocket editor, for testing sync without a GUI
+///
+/// Listens on a free local port in a thread, serves keys of its tracks to a connected demo
+/// and sends scripted events. Events are dropped while no demo is connected.
+pub struct MockEditor {
+    address: String,
+    commands: Sender<Command>,
+    status: Arc<Mutex<Status>>,
+}
+
+impl MockEditor {
+    pub fn start(tracks: &[Track]) -> Result<Self> {
+        let listener =
+            TcpListener::bind("127.0.0.1:0").context("Failed to listen for a Rocket client")?;
+        listener.set_nonblocking(true)?;
+        let address = listener.local_addr()?.to_string();
+        let tracks = TrackData::from_tracks(tracks)?;
+        let (commands, receiver) = mpsc::channel();
+        let status = Arc::new(Mutex::new(Status::default()));
+
+        let server = Server {
+            listener,
+            connection: None,
+            tracks,
+            status: status.clone(),
+        };
+        std::thread::spawn(move || server.run(receiver));
+
+        Ok(Self {
+            address,
+            commands,
+            status,
+        })
+    }
+
+    /// Address to connect to, see [`crate::DemoSync::with_rocket`]
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn set_row(&self, row: u32) {
+        self.send(Command::SetRow(row));
+    }
+
+    pub fn pause(&self, pause: bool) {
+        self.send(Command::Pause(pause));
+    }
+
+    pub fn save_tracks(&self) {
+        self.send(Command::SaveTracks);
+    }
+
+    pub fn set_key(&self, track: &str, row: u32, value: f32, interpolation: Interpolation) {
+        let key = KeyData {
+            row,
+            value,
+            interpolation,
+        };
+        self.send(Command::SetKey(track.to_owned(), key));
+    }
+
+    pub fn delete_key(&self, track: &str, row: u32) {
+        self.send(Command::DeleteKey(track.to_owned(), row));
+    }
+
+    /// Close the connection like a closed editor, the demo can connect again
+    pub fn disconnect(&self) {
+        self.send(Command::Disconnect);
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.status.lock().unwrap().connected
+    }
+
+    /// Number of connections accepted since start
+    pub fn connections(&self) -> usize {
+        self.status.lock().unwrap().connections
+    }
+
+    /// Last row sent by the demo
+    pub fn row(&self) -> Option<u32> {
+        self.status.lock().unwrap().row
+    }
+
+    /// Tracks requested by the demo during the current connection
+    pub fn requested_tracks(&self) -> Vec<String> {
+        self.status.lock().unwrap().requested.clone()
+    }
+
+    fn send(&self, command: Command) {
+        // The server thread only stops when self is dropped
+        self.commands.send(command).unwrap();
+    }
+}
+
+enum Message {
+    GetTrack(String),
+    SetRow(u32),
+}
+
+/// Parse a message from the demo and its length, if the buffer has a complete one
+fn parse(buffer: &[u8]) -> Result<Option<(Message, usize)>> {
+    let read_u32 = |at: usize| {
+        buffer
+            .get(at..at + 4)
+            .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+    };
+    Ok(match buffer.first() {
+        None => None,
+        Some(&GET_TRACK) => read_u32(1).and_then(|len| {
+            let end = 5 + len as usize;
+            let name = buffer.get(5..end)?;
+            Some((
+                Message::GetTrack(String::from_utf8_lossy(name).into_owned()),
+                end,
+            ))
+        }),
+        Some(&SET_ROW) => read_u32(1).map(|row| (Message::SetRow(row), 5)),
+        Some(command) => return Err(anyhow!("Unknown command {}", command)),
+    })
+}
+
+struct Connection {
+    stream: TcpStream,
+    // Received bytes which don't make a complete message yet
+    buffer: Vec<u8>,
+}
+
+struct Server {
+    listener: TcpListener,
+    connection: Option<Connection>,
+    tracks: Vec<TrackData>,
+    status: Arc<Mutex<Status>>,
+}
+
+impl Server {
+    fn run(mut self, commands: Receiver<Command>) {
+        loop {
+            loop {
+                match commands.try_recv() {
+                    Ok(command) => self.apply(command),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            }
+
+            let result = if self.connection.is_none() {
+                self.accept()
+            } else {
+                self.receive()
+            };
+            if let Err(e) = result {
+                log::warn!("Mock Rocket editor: {:?}", e);
+                self.close();
+            }
+
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn accept(&mut self) -> Result<()> {
+        let stream = match self.listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        // Handshake before switching to polling
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+        let mut greeting = [0; CLIENT_GREETING.len()];
+        (&stream)
+            .read_exact(&mut greeting)
+            .context("Handshake failed")?;
+        if greeting != CLIENT_GREETING {
+            return Err(anyhow!("Unexpected greeting {:?}", greeting));
+        }
+        (&stream).write_all(SERVER_GREETING)?;
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+
+        self.connection = Some(Connection {
+            stream,
+            buffer: Vec::new(),
+        });
+        let mut status = self.status.lock().unwrap();
+        status.connected = true;
+        status.connections += 1;
+        status.requested.clear();
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        self.connection = None;
+        self.status.lock().unwrap().connected = false;
+    }
+
+    fn receive(&mut self) -> Result<()> {
+        let mut buf = [0; 1024];
+        while let Some(connection) = &mut self.connection {
+            match connection.stream.read(&mut buf) {
+                Ok(0) => self.close(),
+                Ok(len) => connection.buffer.extend_from_slice(&buf[..len]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        // Handle complete messages
+        while let Some(connection) = &mut self.connection {
+            match parse(&connection.buffer)? {
+                Some((message, len)) => {
+                    connection.buffer.drain(..len);
+                    match message {
+                        Message::GetTrack(name) => self.get_track(name)?,
+                        Message::SetRow(row) => self.status.lock().unwrap().row = Some(row),
+                    }
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Number the track in the order of requests and send its keys
+    fn get_track(&mut self, name: String) -> Result<()> {
+        let index = {
+            let mut status = self.status.lock().unwrap();
+            status.requested.push(name.clone());
+            status.requested.len() - 1
+        };
+        let keys = self
+            .tracks
+            .iter()
+            .find(|track| track.name == name)
+            .map(|track| track.keys.clone())
+            .unwrap_or_default();
+        for key in keys {
+            self.send(&set_key_message(index, key))?;
+        }
+        Ok(())
+    }
+
+    fn track_index(&self, name: &str) -> Option<usize> {
+        let status = self.status.lock().unwrap();
+        status
+            .requested
+            .iter()
+            .position(|requested| requested == name)
+    }
+
+    fn track_mut(&mut self, name: &str) -> &mut TrackData {
+        match self.tracks.iter().position(|track| track.name == name) {
+            Some(i) => &mut self.tracks[i],
+            None => {
+                self.tracks.push(TrackData {
+                    name: name.to_owned(),
+                    keys: Vec::new(),
+                });
+                self.tracks.last_mut().unwrap()
+            }
+        }
+    }
+
+    fn apply(&mut self, command: Command) {
+        let message = match command {
+            Command::SetRow(row) => Some([&[SET_ROW][..], &row.to_be_bytes()].concat()),
+            Command::Pause(pause) => Some(vec![PAUSE, u8::from(pause)]),
+            Command::SaveTracks => Some(vec![SAVE_TRACKS]),
+            Command::SetKey(name, key) => {
+                let keys = &mut self.track_mut(&name).keys;
+                keys.retain(|k| k.row != key.row);
+                keys.push(key);
+                keys.sort_by_key(|k| k.row);
+                self.track_index(&name)
+                    .map(|index| set_key_message(index, key))
+            }
+            Command::DeleteKey(name, row) => {
+                self.track_mut(&name).keys.retain(|k| k.row != row);
+                self.track_index(&name).map(|index| {
+                    [
+                        &[DELETE_KEY][..],
+                        &(index as u32).to_be_bytes(),
+                        &row.to_be_bytes(),
+                    ]
+                    .concat()
+                })
+            }
+            Command::Disconnect => {
+                self.close();
+                None
+            }
+        };
+        if let Some(message) = message {
+            if let Err(e) = self.send(&message) {
+                log::warn!("Mock Rocket editor: {:?}", e);
+                self.close();
+            }
+        }
+    }
+
+    fn send(&mut self, message: &[u8]) -> Result<()> {
+        if let Some(connection) = &mut self.connection {
+            // Rocket clients read whole messages, so write them at once
+            connection.stream.set_nonblocking(false)?;
+            connection.stream.write_all(message)?;
+            connection.stream.set_nonblocking(true)?;
+        }
+        Ok(())
+    }
+}
+
+fn set_key_message(index: usize, key: KeyData) -> Vec<u8> {
+    [
+        &[SET_KEY][..],
+        &(index as u32).to_be_bytes(),
+        &key.row.to_be_bytes(),
+        &key.value.to_be_bytes(),
+        &[key.interpolation as u8],
+    ]
+    .concat()
+}
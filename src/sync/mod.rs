@@ -1,111 +1,330 @@
+mod check;
 mod frame_counter;
+#[cfg(debug_assertions)]
+mod mock_editor;
+mod params;
+mod rocket_xml;
+mod track_file;
 
-use crate::Player;
-use color_space::Hsv;
+use crate::{AnalysisFrame, BeatInfo, Player, TrackerPosition};
+use anyhow::Result;
+pub use check::{check_tracks, TrackReport};
 use frame_counter::FrameCounter;
-use glam::*;
+#[cfg(debug_assertions)]
+pub use mock_editor::MockEditor;
+pub(crate) use params::sync_params;
+pub use params::{FieldTracks, ParamTracks, ResolveTrack, SyncParams, SyncValue};
+use rust_rocket::track::Track;
+#[cfg(debug_assertions)]
+use std::sync::mpsc::Receiver;
+pub use track_file::convert as convert_tracks;
 
 const TRACKS_FILE: &str = "tracks.bin";
+// Rocket editor's save file, played when there's no tracks.bin
+const ROCKET_FILE: &str = "sync.rocket";
+// Where the Rocket editor listens
+#[cfg(debug_assertions)]
+const ROCKET_ADDRESS: &str = "localhost:1338";
+// How long the calibration flash lasts after each click
+const FLASH_SECS: f32 = 0.1;
+
+/// Track looked up once with [`DemoSync::track`], read with [`DemoSync::value`]
+#[derive(Clone, Copy, Debug)]
+pub struct TrackId(usize);
 
 pub struct DemoSync {
     row: f32,
     beats_per_sec: f32,
     rows_per_beat: f32,
-    beat: f32,
+    analysis: AnalysisFrame,
+    loudness: f32,
+    beat_info: BeatInfo,
+    tracker_position: Option<TrackerPosition>,
     frame_counter: Option<FrameCounter>,
+    gain_track: Option<TrackId>,
+    calibration: bool,
+    flash: f32,
+    // Tracks to play, and to fall back to in debug builds when Rocket is not connected
+    tracks: rust_rocket::RocketPlayer,
+    // Names of tracks given a TrackId, and their keys in `tracks`
+    track_names: Vec<String>,
+    resolved: Vec<Option<Track>>,
     #[cfg(debug_assertions)]
-    rocket: rust_rocket::RocketClient,
-    #[cfg(not(debug_assertions))]
-    rocket: rust_rocket::RocketPlayer,
+    rocket: Option<rust_rocket::RocketClient>,
+    #[cfg(debug_assertions)]
+    rocket_address: String,
+    #[cfg(debug_assertions)]
+    connector: Option<Receiver<rust_rocket::RocketClient>>,
+    // Tracks edited in Rocket before the connection was lost
+    #[cfg(debug_assertions)]
+    edited_tracks: Option<Vec<Track>>,
+    #[cfg(debug_assertions)]
+    save_path: std::path::PathBuf,
 }
 
+/// Keep trying to connect to Rocket in a thread, without blocking rendering
 #[cfg(debug_assertions)]
-fn connect() -> rust_rocket::RocketClient {
-    loop {
-        if let Ok(rocket) = rust_rocket::RocketClient::new() {
-            return rocket;
+fn connect_in_background(address: String) -> Receiver<rust_rocket::RocketClient> {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || loop {
+        if let Ok(rocket) = rust_rocket::RocketClient::connect(address.as_str()) {
+            sender.send(rocket).ok();
+            return;
         }
-        log::info!("Cannot connect to Rocket, retrying in a sec...");
         std::thread::sleep(std::time::Duration::from_secs(1));
+    });
+    receiver
+}
+
+/// Load saved tracks from tracks.bin or the Rocket editor's save file
+pub fn load_tracks() -> Result<Vec<Track>> {
+    #[cfg(debug_assertions)]
+    {
+        let path = std::path::Path::new(crate::RESOURCES_PATH).join(TRACKS_FILE);
+        let path = if path.exists() {
+            path
+        } else {
+            ROCKET_FILE.into()
+        };
+        log::info!("Loading {}", path.display());
+        track_file::read(&path)
+    }
+    #[cfg(not(debug_assertions))]
+    {
+        use anyhow::Context;
+        match crate::RESOURCES_DIR.get_file(TRACKS_FILE) {
+            Some(file) => {
+                log::info!("Loading {}", TRACKS_FILE);
+                bincode::deserialize(file.contents()).context("Failed to deserialize tracks")
+            }
+            None => {
+                log::info!("Loading {}", ROCKET_FILE);
+                rocket_xml::parse(include_bytes!(concat!(
+                    env!("CARGO_MANIFEST_DIR"),
+                    "/sync.rocket"
+                )))
+                .context("Failed to parse sync.rocket")
+            }
+        }
     }
 }
 
 impl DemoSync {
+    #[cfg(not(debug_assertions))]
     pub fn new(bpm: f32, rows_per_beat: f32, benchmark: bool) -> Self {
-        #[cfg(debug_assertions)]
-        let rocket = {
-            log::info!("Connecting to rocket tracker");
-            connect()
-        };
-        #[cfg(not(debug_assertions))]
-        let rocket = {
-            log::info!("Loading {}", TRACKS_FILE);
-            let file = crate::RESOURCES_DIR
-                .get_file(TRACKS_FILE)
-                .expect("File not present in binary. This is a bug.");
-            let tracks =
-                bincode::deserialize_from(file.contents()).expect("Failed to deserialize tracks");
-            rust_rocket::RocketPlayer::new(tracks)
-        };
+        let tracks = load_tracks().expect("Failed to load sync tracks");
+        Self::with_tracks(bpm, rows_per_beat, benchmark, tracks)
+    }
 
+    #[cfg(debug_assertions)]
+    pub fn new(bpm: f32, rows_per_beat: f32, benchmark: bool) -> Self {
+        Self::with_rocket(bpm, rows_per_beat, benchmark, ROCKET_ADDRESS)
+    }
+
+    /// Connect to a Rocket editor at `address`, eg. a [`MockEditor`](crate::MockEditor)
+    #[cfg(debug_assertions)]
+    pub fn with_rocket(bpm: f32, rows_per_beat: f32, benchmark: bool, address: &str) -> Self {
+        log::info!("Connecting to rocket tracker at {}", address);
+        let tracks = load_tracks().unwrap_or_else(|e| {
+            log::warn!("{:?}", e);
+            Vec::new()
+        });
+        Self::with_tracks(bpm, rows_per_beat, benchmark, tracks).connect(address)
+    }
+
+    /// Play saved tracks until a Rocket editor at `address` is running
+    #[cfg(debug_assertions)]
+    fn connect(mut self, address: &str) -> Self {
+        self.rocket_address = address.to_owned();
+        self.rocket = rust_rocket::RocketClient::connect(address).ok();
+        if self.rocket.is_none() {
+            log::info!("Cannot connect to Rocket, playing saved tracks until it's running");
+            self.connector = Some(connect_in_background(self.rocket_address.clone()));
+        }
+        self
+    }
+
+    fn with_tracks(bpm: f32, rows_per_beat: f32, benchmark: bool, tracks: Vec<Track>) -> Self {
         Self {
             row: 0.,
             beats_per_sec: bpm / 60.,
             rows_per_beat,
-            beat: 0.,
+            analysis: AnalysisFrame::default(),
+            loudness: 0.,
+            beat_info: BeatInfo::default(),
+            tracker_position: None,
             frame_counter: benchmark.then(FrameCounter::new),
-            rocket,
+            gain_track: None,
+            calibration: false,
+            flash: 0.,
+            tracks: rust_rocket::RocketPlayer::new(tracks),
+            track_names: Vec::new(),
+            resolved: Vec::new(),
+            #[cfg(debug_assertions)]
+            rocket: None,
+            #[cfg(debug_assertions)]
+            rocket_address: ROCKET_ADDRESS.into(),
+            #[cfg(debug_assertions)]
+            connector: None,
+            #[cfg(debug_assertions)]
+            edited_tracks: None,
+            #[cfg(debug_assertions)]
+            save_path: [crate::RESOURCES_PATH, TRACKS_FILE].into_iter().collect(),
         }
     }
 
+    /// Look up a track to read with [`DemoSync::value`]
+    pub fn track(&mut self, name: &str) -> TrackId {
+        if let Some(i) = self.track_names.iter().position(|n| n == name) {
+            return TrackId(i);
+        }
+        self.track_names.push(name.to_owned());
+        self.resolved.push(self.tracks.get_track(name).cloned());
+        TrackId(self.track_names.len() - 1)
+    }
+
+    /// Look up the tracks again after they were replaced
     #[cfg(debug_assertions)]
-    pub fn get(&mut self, track: &str) -> f32 {
-        loop {
-            if let Ok(track) = self.rocket.get_track_mut(track) {
-                return track.get_value(self.row as f32);
+    fn resolve_tracks(&mut self) {
+        self.resolved = self
+            .track_names
+            .iter()
+            .map(|name| self.tracks.get_track(name).cloned())
+            .collect();
+    }
+
+    /// Value of a track at the current row, like [`DemoSync::get`] without finding the
+    /// track by name
+    #[cfg(debug_assertions)]
+    pub fn value(&mut self, track: TrackId) -> f32 {
+        // The Rocket client only finds its tracks by name
+        if let Some(rocket) = &mut self.rocket {
+            match rocket.get_track_mut(&self.track_names[track.0]) {
+                Ok(track) => return track.get_value(self.row),
+                Err(_) => self.disconnect(),
             }
-            self.reconnect();
         }
+        self.resolved[track.0]
+            .as_ref()
+            .map_or(0., |track| track.get_value(self.row))
     }
 
     #[cfg(not(debug_assertions))]
-    pub fn get(&mut self, track: &str) -> f32 {
-        self.rocket
-            .get_track(track)
-            .unwrap_or_else(|| panic!("Sync track {} is not present. This is a bug, sorry.", track))
-            .get_value(self.row as f32)
+    pub fn value(&mut self, track: TrackId) -> f32 {
+        self.resolved[track.0]
+            .as_ref()
+            .unwrap_or_else(|| {
+                panic!(
+                    "Sync track {} is not present. This is a bug, sorry.",
+                    self.track_names[track.0]
+                )
+            })
+            .get_value(self.row)
     }
 
-    pub fn get_hsv(&mut self, track: &str, components: Option<[&str; 3]>) -> Hsv {
-        let [x, y, z] = components.unwrap_or([":hue", ":saturation", ":value"]);
-        Hsv::new(
-            self.get(&format!("{track}{x}")) as f64,
-            self.get(&format!("{track}{y}")) as f64,
-            self.get(&format!("{track}{z}")) as f64,
-        )
+    pub fn get_beat(&self) -> f32 {
+        self.analysis.bass
     }
 
-    pub fn get_vec3(&mut self, track: &str, components: Option<[&str; 3]>) -> Vec3 {
-        let [x, y, z] = components.unwrap_or([".x", ".y", ".z"]);
-        vec3(
-            self.get(&format!("{track}{x}")),
-            self.get(&format!("{track}{y}")),
-            self.get(&format!("{track}{z}")),
-        )
+    /// RMS level of all channels
+    pub fn get_rms(&self) -> f32 {
+        self.analysis.rms
     }
 
-    pub fn get_vec4(&mut self, track: &str, components: Option<[&str; 4]>) -> Vec4 {
-        let [x, y, z, w] = components.unwrap_or([".x", ".y", ".z", ".w"]);
-        vec4(
-            self.get(&format!("{track}{x}")),
-            self.get(&format!("{track}{y}")),
-            self.get(&format!("{track}{z}")),
-            self.get(&format!("{track}{w}")),
-        )
+    /// Largest absolute sample value of any channel
+    pub fn get_peak(&self) -> f32 {
+        self.analysis.peak
     }
 
-    pub fn get_beat(&self) -> f32 {
-        self.beat
+    /// RMS level smoothed with attack and release, see [`Player::set_loudness_envelope`]
+    pub fn get_loudness(&self) -> f32 {
+        self.loudness
+    }
+
+    /// Position within the current beat of the detected beat grid, from 0 to 1
+    pub fn get_beat_phase(&self) -> f32 {
+        self.beat_info.beat_phase
+    }
+
+    /// Position within the current bar of the detected beat grid, from 0 to 1
+    pub fn get_bar_phase(&self) -> f32 {
+        self.beat_info.bar_phase
+    }
+
+    /// Normalized onset strength, from 0 to 1
+    pub fn get_onset_strength(&self) -> f32 {
+        self.beat_info.onset_strength
+    }
+
+    /// Seconds since the last detected kick
+    pub fn get_since_kick(&self) -> f32 {
+        self.beat_info.since_kick
+    }
+
+    /// RMS level of the left channel
+    pub fn get_level_left(&self) -> f32 {
+        self.analysis.levels.left
+    }
+
+    /// RMS level of the right channel
+    pub fn get_level_right(&self) -> f32 {
+        self.analysis.levels.right
+    }
+
+    /// Stereo width from 0 (mono) to 1 (opposite phase channels)
+    pub fn get_stereo_width(&self) -> f32 {
+        self.analysis.levels.width
+    }
+
+    /// Order, pattern and row when playing a tracker module
+    pub fn get_tracker_position(&self) -> Option<TrackerPosition> {
+        self.tracker_position
+    }
+
+    /// Drive the player's volume from a sync track, eg. `audio:gain`
+    pub fn set_gain_track(&mut self, track: Option<&str>) {
+        // Don't panic later in release builds when the track was never saved
+        #[cfg(not(debug_assertions))]
+        if let Some(name) = track {
+            if self.tracks.get_track(name).is_none() {
+                log::warn!(
+                    "Sync track {} is not present, not using it for volume",
+                    name
+                );
+                return;
+            }
+        }
+        self.gain_track = track.map(|name| self.track(name));
+    }
+
+    /// Flash the screen on every click of [`Player::click_track`]
+    pub fn set_calibration(&mut self, calibration: bool) {
+        self.calibration = calibration;
+    }
+
+    /// Calibration flash brightness from 0 to 1
+    pub fn get_flash(&self) -> f32 {
+        self.flash
+    }
+
+    /// Row of the current frame
+    pub fn get_row(&self) -> f32 {
+        self.row
+    }
+
+    /// Loop playback between start and end rows, or play normally with `None`
+    pub fn set_loop_rows(&self, player: &mut Player, rows: Option<(f32, f32)>) {
+        player.set_loop(rows.map(|(start, end)| {
+            (
+                self.row_to_secs(player, start),
+                self.row_to_secs(player, end),
+            )
+        }));
+    }
+
+    /// Frequency band magnitudes of mono downmix in the order of [`Band::ALL`]
+    pub fn get_bands(&self) -> &[f32] {
+        &self.analysis.bands
     }
 
     #[cfg(debug_assertions)]
@@ -116,30 +335,27 @@ impl DemoSync {
         // to avoid changing the tracker's position when the user is changing it manually
         let mut seeking = false;
 
-        loop {
-            if let Ok(result) = self.rocket.poll_events() {
-                if let Some(event) = result {
-                    match event {
-                        Event::SetRow(row) => {
-                            player.seek(self.row_to_secs(row as f32));
-                            seeking = true;
-                        }
-                        Event::Pause(state) => {
-                            if state {
-                                player.pause();
-                            } else {
-                                player.play();
-                            }
-                        }
-                        Event::SaveTracks => {
-                            self.save_tracks();
+        while let Some(rocket) = &mut self.rocket {
+            match rocket.poll_events() {
+                Ok(Some(event)) => match event {
+                    Event::SetRow(row) => {
+                        let secs = self.row_to_secs(player, row as f32);
+                        player.seek(secs);
+                        seeking = true;
+                    }
+                    Event::Pause(state) => {
+                        if state {
+                            player.pause();
+                        } else {
+                            player.play();
                         }
                     }
-                } else {
-                    break;
-                }
-            } else {
-                self.reconnect();
+                    Event::SaveTracks => {
+                        self.save_tracks();
+                    }
+                },
+                Ok(None) => break,
+                Err(_) => self.disconnect(),
             }
         }
 
@@ -153,12 +369,18 @@ impl DemoSync {
     /// Returns true if the demo should should end
     pub fn update(&mut self, player: &mut Player) -> bool {
         if let Some(frame_counter) = &mut self.frame_counter {
-            frame_counter.tick();
+            frame_counter.tick(player);
         }
 
-        // Poll rocket events
+        // Step the player's clock if it's not driven by audio output
+        player.advance_frame();
+
+        // Pick up a connection made in the background, and poll rocket events
         #[cfg(debug_assertions)]
-        let seeking = self.poll_events(player);
+        let seeking = {
+            self.poll_connection();
+            self.poll_events(player)
+        };
 
         // This frame's time to render at
         let secs = player.time_secs();
@@ -170,56 +392,104 @@ impl DemoSync {
         }
 
         // Set frame's row for Rocket track gets
-        self.row = self.secs_to_row(secs);
+        self.row = self.secs_to_row(player, secs);
+
+        // Flash in time with the click track
+        self.flash = if self.calibration {
+            (1. - (secs % crate::player::CLICK_INTERVAL) / FLASH_SECS).max(0.)
+        } else {
+            0.
+        };
+
+        // Volume automation
+        if let Some(track) = self.gain_track {
+            let gain = self.value(track);
+            player.set_volume(gain);
+        }
 
         // Update rocket tracker's position when necessary
         #[cfg(debug_assertions)]
         if player.is_playing() && !seeking {
-            loop {
-                if self.rocket.set_row(self.row as u32).is_ok() {
-                    break;
+            if let Some(rocket) = &mut self.rocket {
+                if rocket.set_row(self.row as u32).is_err() {
+                    self.disconnect();
                 }
-                self.reconnect();
             }
         }
 
-        // Absolute energy in low freq range is a pretty good musical beat value
-        self.beat = player.bass_psd(secs);
+        // Music analysis for the shaders, sampled at the current playback time
+        self.analysis = player.analysis(secs);
+        self.loudness = player.loudness_envelope(secs);
+        self.beat_info = player.beat_info(secs);
+        self.tracker_position = player.tracker_position(secs);
 
         false
     }
 
+    /// Save tracks edited in Rocket to `path` instead of resources/tracks.bin
     #[cfg(debug_assertions)]
-    fn reconnect(&mut self) {
-        log::error!("Connection lost, reconnecting");
-        self.rocket = connect()
+    pub fn set_save_path(&mut self, path: impl Into<std::path::PathBuf>) {
+        self.save_path = path.into();
+    }
+
+    /// True when a Rocket editor is connected and controls playback
+    #[cfg(debug_assertions)]
+    pub fn is_connected(&self) -> bool {
+        self.rocket.is_some()
+    }
+
+    #[cfg(debug_assertions)]
+    fn poll_connection(&mut self) {
+        if let Some(rocket) = self.connector.as_ref().and_then(|c| c.try_recv().ok()) {
+            log::info!("Connected to Rocket");
+            self.rocket = Some(rocket);
+            self.connector = None;
+        }
+    }
+
+    /// Keep playing the tracks from the lost connection and reconnect in the background
+    #[cfg(debug_assertions)]
+    fn disconnect(&mut self) {
+        if let Some(rocket) = self.rocket.take() {
+            log::error!("Connection lost, reconnecting in the background");
+            let tracks = rocket.save_tracks();
+            self.tracks = rust_rocket::RocketPlayer::new(tracks.clone());
+            self.resolve_tracks();
+            self.edited_tracks = Some(tracks);
+            self.connector = Some(connect_in_background(self.rocket_address.clone()));
+        }
     }
 
     #[cfg(debug_assertions)]
     fn save_tracks(&mut self) {
-        log::info!("Saving {}/{}", crate::RESOURCES_PATH, TRACKS_FILE);
-        let tracks = self.rocket.save_tracks();
+        // Don't overwrite the file with tracks which were never edited
+        let tracks = match (&self.rocket, &self.edited_tracks) {
+            (Some(rocket), _) => rocket.save_tracks(),
+            (None, Some(tracks)) => tracks.clone(),
+            (None, None) => return,
+        };
+        log::info!("Saving {}", self.save_path.display());
         let file = std::fs::OpenOptions::new()
             .write(true)
             .create(true)
             .truncate(true)
-            .open(
-                [crate::RESOURCES_PATH, TRACKS_FILE]
-                    .into_iter()
-                    .collect::<std::path::PathBuf>(),
-            )
+            .open(&self.save_path)
             .expect("Cannot open track file");
         bincode::serialize_into(file, &tracks).expect("Cannot serialize tracks");
     }
 
-    #[cfg(debug_assertions)]
-    fn row_to_secs(&self, row: f32) -> f32 {
-        let beat = row / self.rows_per_beat;
-        beat / self.beats_per_sec
+    // Rocket rows are module rows when playing a tracker module, otherwise they follow the BPM
+    fn row_to_secs(&self, player: &Player, row: f32) -> f32 {
+        player.tracker_row_secs(row).unwrap_or_else(|| {
+            let beat = row / self.rows_per_beat;
+            beat / self.beats_per_sec
+        })
     }
 
-    fn secs_to_row(&self, secs: f32) -> f32 {
-        secs * self.beats_per_sec * self.rows_per_beat
+    fn secs_to_row(&self, player: &Player, secs: f32) -> f32 {
+        player
+            .tracker_row(secs)
+            .unwrap_or(secs * self.beats_per_sec * self.rows_per_beat)
     }
 }
 
@@ -229,3 +499,139 @@ impl Drop for DemoSync {
         self.save_tracks();
     }
 }
+
+#[cfg(all(test, debug_assertions))]
+mod tests {
+    use super::*;
+    use crate::{AudioBackend, MockEditor};
+    use rust_rocket::{interpolation::Interpolation, track::Key};
+    use std::{
+        path::{Path, PathBuf},
+        time::{Duration, Instant},
+    };
+
+    const TRACK: &str = "test:value";
+    const FRAME: Duration = Duration::from_micros(16667);
+    // Long enough for reconnecting, which is retried every second
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Demo connected to a mock editor which has keys on rows 0 and 64 of [`TRACK`]
+    struct Session {
+        editor: MockEditor,
+        sync: DemoSync,
+        track: TrackId,
+        player: Player,
+        // Dropped after `sync`, which saves once more when it's dropped
+        save_path: SavedFile,
+    }
+
+    struct SavedFile(PathBuf);
+
+    impl Drop for SavedFile {
+        fn drop(&mut self) {
+            std::fs::remove_file(&self.0).ok();
+        }
+    }
+
+    impl Session {
+        fn start(name: &str) -> Self {
+            let mut track = Track::new(TRACK);
+            track.set_key(Key::new(0, 1., Interpolation::Step));
+            track.set_key(Key::new(64, 2., Interpolation::Step));
+            let editor = MockEditor::start(&[track]).unwrap();
+
+            // No saved tracks, everything comes from the editor
+            let mut sync =
+                DemoSync::with_tracks(120., 8., false, Vec::new()).connect(editor.address());
+            let save_path =
+                std::env::temp_dir().join(format!("sync-{}-{}.bin", name, std::process::id()));
+            sync.set_save_path(&save_path);
+            let track = sync.track(TRACK);
+            assert!(sync.is_connected());
+            Self {
+                editor,
+                sync,
+                track,
+                player: Player::click_track(AudioBackend::FixedStep(FRAME)).unwrap(),
+                save_path: SavedFile(save_path),
+            }
+        }
+
+        fn frame(&mut self) {
+            self.sync.update(&mut self.player);
+            // Give the editor's thread time to answer
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        fn value(&mut self) -> f32 {
+            self.sync.value(self.track)
+        }
+
+        fn wait_for(&mut self, what: &str, condition: impl Fn(&mut Self) -> bool) {
+            let start = Instant::now();
+            while start.elapsed() < TIMEOUT {
+                self.frame();
+                if condition(self) {
+                    return;
+                }
+            }
+            panic!("Timed out waiting for {}", what);
+        }
+    }
+
+    fn saved_value(path: &Path, row: f32) -> Option<f32> {
+        let saved: Vec<Track> = bincode::deserialize(&std::fs::read(path).ok()?).ok()?;
+        saved
+            .iter()
+            .find(|track| track.get_name() == TRACK)
+            .map(|track| track.get_value(row))
+    }
+
+    #[test]
+    fn follows_editor() {
+        let mut s = Session::start("follow");
+        s.wait_for("keys from the editor", |s| s.value() == 1.);
+
+        s.editor.set_row(64);
+        s.wait_for("seek", |s| {
+            s.sync.get_row() == 64. && s.value() == 2. && !s.player.is_playing()
+        });
+
+        s.editor.pause(false);
+        s.wait_for("playback", |s| {
+            s.player.is_playing() && s.editor.row() > Some(64)
+        });
+
+        s.editor.pause(true);
+        s.wait_for("pause", |s| !s.player.is_playing());
+        let row = s.sync.get_row();
+        for _ in 0..10 {
+            s.frame();
+        }
+        assert_eq!(s.sync.get_row(), row);
+
+        s.editor.set_key(TRACK, 64, 5., Interpolation::Step);
+        s.wait_for("key edit", |s| s.value() == 5.);
+    }
+
+    #[test]
+    fn keeps_edits_over_reconnection() {
+        let mut s = Session::start("reconnect");
+        s.editor.set_row(64);
+        s.wait_for("seek", |s| s.value() == 2.);
+        s.editor.set_key(TRACK, 64, 5., Interpolation::Step);
+        s.wait_for("key edit", |s| s.value() == 5.);
+
+        s.editor.save_tracks();
+        s.wait_for("save", |s| saved_value(&s.save_path.0, 64.) == Some(5.));
+
+        s.editor.disconnect();
+        s.wait_for("disconnection", |s| !s.sync.is_connected());
+        assert_eq!(s.value(), 5.);
+
+        s.wait_for("reconnection", |s| {
+            s.sync.is_connected() && s.editor.connections() == 2
+        });
+        s.wait_for("keys after reconnection", |s| s.value() == 5.);
+    }
+}
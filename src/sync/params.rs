@@ -0,0 +1,188 @@
+use super::{DemoSync, TrackId};
+use color_space::Hsv;
+use glam::*;
+use std::{marker::PhantomData, slice::Iter};
+
+/// Looks up tracks by name once, so that reading them later doesn't
+pub trait ResolveTrack {
+    fn track(&mut self, name: &str) -> TrackId;
+}
+
+impl ResolveTrack for DemoSync {
+    fn track(&mut self, name: &str) -> TrackId {
+        DemoSync::track(self, name)
+    }
+}
+
+/// Collects the names of tracks without reading them, eg. to check that they exist
+impl ResolveTrack for Vec<String> {
+    fn track(&mut self, name: &str) -> TrackId {
+        self.push(name.to_owned());
+        TrackId(self.len() - 1)
+    }
+}
+
+/// Value read from one or more consecutive sync tracks
+pub trait SyncValue: Sized {
+    /// Suffixes of the component tracks when a field only names its base track
+    const COMPONENTS: &'static [&'static str];
+
+    fn read(sync: &mut DemoSync, tracks: &mut Iter<'_, TrackId>) -> Self;
+}
+
+impl SyncValue for f32 {
+    const COMPONENTS: &'static [&'static str] = &[""];
+
+    fn read(sync: &mut DemoSync, tracks: &mut Iter<'_, TrackId>) -> Self {
+        sync.value(*tracks.next().expect("Ran out of sync tracks"))
+    }
+}
+
+impl SyncValue for Vec3 {
+    const COMPONENTS: &'static [&'static str] = &[".x", ".y", ".z"];
+
+    fn read(sync: &mut DemoSync, tracks: &mut Iter<'_, TrackId>) -> Self {
+        vec3(
+            f32::read(sync, tracks),
+            f32::read(sync, tracks),
+            f32::read(sync, tracks),
+        )
+    }
+}
+
+impl SyncValue for Vec4 {
+    const COMPONENTS: &'static [&'static str] = &[".x", ".y", ".z", ".w"];
+
+    fn read(sync: &mut DemoSync, tracks: &mut Iter<'_, TrackId>) -> Self {
+        vec4(
+            f32::read(sync, tracks),
+            f32::read(sync, tracks),
+            f32::read(sync, tracks),
+            f32::read(sync, tracks),
+        )
+    }
+}
+
+impl SyncValue for Hsv {
+    const COMPONENTS: &'static [&'static str] = &[":hue", ":saturation", ":value"];
+
+    fn read(sync: &mut DemoSync, tracks: &mut Iter<'_, TrackId>) -> Self {
+        Hsv::new(
+            f32::read(sync, tracks) as f64,
+            f32::read(sync, tracks) as f64,
+            f32::read(sync, tracks) as f64,
+        )
+    }
+}
+
+/// Tracks of a field, either a base name which gets the value's [`SyncValue::COMPONENTS`]
+/// or a name for each component
+pub trait FieldTracks {
+    fn push_names<T: SyncValue>(self, prefix: &str, names: &mut Vec<String>);
+}
+
+fn track_name(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{prefix}:{name}")
+    }
+}
+
+impl FieldTracks for &str {
+    fn push_names<T: SyncValue>(self, prefix: &str, names: &mut Vec<String>) {
+        for component in T::COMPONENTS {
+            names.push(track_name(prefix, &format!("{self}{component}")));
+        }
+    }
+}
+
+impl<const N: usize> FieldTracks for [&str; N] {
+    fn push_names<T: SyncValue>(self, prefix: &str, names: &mut Vec<String>) {
+        assert_eq!(
+            N,
+            T::COMPONENTS.len(),
+            "Wrong number of tracks for {:?}",
+            self
+        );
+        for name in self {
+            names.push(track_name(prefix, name));
+        }
+    }
+}
+
+/// Struct of values read from tracks under a common prefix, implemented with [`sync_params`]
+pub trait SyncParams: Sized {
+    fn push_names(prefix: &str, names: &mut Vec<String>);
+
+    /// Read each field from `tracks` in the order of [`SyncParams::push_names`]
+    fn read(sync: &mut DemoSync, tracks: &mut Iter<'_, TrackId>) -> Self;
+}
+
+/// Tracks of a [`SyncParams`] struct, resolved once so that reading doesn't look up names
+pub struct ParamTracks<T> {
+    tracks: Vec<TrackId>,
+    params: PhantomData<T>,
+}
+
+impl<T: SyncParams> ParamTracks<T> {
+    /// Tracks named `prefix:field`, or just `field` when the prefix is empty
+    pub fn new(prefix: &str, resolve: &mut impl ResolveTrack) -> Self {
+        let mut names = Vec::new();
+        T::push_names(prefix, &mut names);
+        Self {
+            tracks: names.iter().map(|name| resolve.track(name)).collect(),
+            params: PhantomData,
+        }
+    }
+
+    /// Read all fields at the current row from the sync which resolved the tracks
+    pub fn read(&self, sync: &mut DemoSync) -> T {
+        T::read(sync, &mut self.tracks.iter())
+    }
+}
+
+/// Declare structs of sync values and implement [`SyncParams`] for them.
+///
+/// Each field is read from the track after `=`, which either gets the components of the
+/// value type appended (eg. `pos.x`, `pos.y` and `pos.z` for `pos: Vec3 = "pos"`) or
+/// lists a track for each component:
+///
+/// ```ignore
+/// sync_params! {
+///     struct CameraParams {
+///         fov: f32 = "fov",
+///         pos: Vec3 = "pos",
+///         angles: Vec3 = ["pitch", "yaw", "roll"],
+///     }
+/// }
+/// ```
+macro_rules! sync_params {
+    ($(
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $($field:ident: $ty:ty = $tracks:expr),* $(,)?
+        }
+    )*) => {$(
+        $(#[$meta])*
+        $vis struct $name {
+            $(pub $field: $ty,)*
+        }
+
+        impl $crate::sync::SyncParams for $name {
+            fn push_names(prefix: &str, names: &mut Vec<String>) {
+                $($crate::sync::FieldTracks::push_names::<$ty>($tracks, prefix, names);)*
+            }
+
+            fn read(
+                sync: &mut $crate::sync::DemoSync,
+                tracks: &mut std::slice::Iter<'_, $crate::sync::TrackId>,
+            ) -> Self {
+                Self {
+                    $($field: <$ty as $crate::sync::SyncValue>::read(sync, tracks),)*
+                }
+            }
+        }
+    )*};
+}
+pub(crate) use sync_params;
@@ -0,0 +1,103 @@
+use super::track_file::TrackData;
+use anyhow::{anyhow, Context, Result};
+use rust_rocket::{
+    interpolation::Interpolation,
+    track::{Key, Track},
+};
+use xml::{attribute::OwnedAttribute, escape::escape_str_attribute, reader::XmlEvent, EventReader};
+
+fn attribute<'a>(attributes: &'a [OwnedAttribute], name: &str) -> Result<&'a str> {
+    attributes
+        .iter()
+        .find(|attribute| attribute.name.local_name == name)
+        .map(|attribute| attribute.value.as_str())
+        .with_context(|| format!("Missing attribute {}", name))
+}
+
+/// Rocket stores interpolation as 0-3, `Interpolation::from` would read anything else as step
+fn parse_interpolation(interpolation: &str) -> Result<Interpolation> {
+    match interpolation
+        .parse::<u8>()
+        .with_context(|| format!("Invalid interpolation {}", interpolation))?
+    {
+        n @ 0..=3 => Ok(Interpolation::from(n)),
+        _ => Err(anyhow!("Unknown interpolation {}", interpolation)),
+    }
+}
+
+/// Read tracks from a GNU Rocket editor XML file
+pub fn parse(data: &[u8]) -> Result<Vec<Track>> {
+    let mut tracks: Vec<Track> = Vec::new();
+    for event in EventReader::new(data) {
+        if let XmlEvent::StartElement {
+            name, attributes, ..
+        } = event.context("Failed to parse Rocket XML")?
+        {
+            match name.local_name.as_str() {
+                "track" => tracks.push(Track::new(attribute(&attributes, "name")?)),
+                "key" => {
+                    let track = tracks
+                        .last_mut()
+                        .ok_or_else(|| anyhow!("Key outside of a track"))?;
+                    let row = attribute(&attributes, "row")?;
+                    let value = attribute(&attributes, "value")?;
+                    let interpolation = attribute(&attributes, "interpolation")?;
+                    let row = row
+                        .parse()
+                        .with_context(|| format!("Invalid row {}", row))?;
+                    let value = value
+                        .parse()
+                        .with_context(|| format!("Invalid value {}", value))?;
+                    let interpolation = parse_interpolation(interpolation)
+                        .with_context(|| format!("Track {} row {}", track.get_name(), row))?;
+                    track.set_key(Key::new(row, value, interpolation));
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(tracks)
+}
+
+/// Write tracks in the GNU Rocket editor's format. Editor state like bookmarks is not kept.
+pub fn write(tracks: &[TrackData]) -> String {
+    let rows = tracks
+        .iter()
+        .filter_map(|track| track.keys.last())
+        .map(|key| key.row + 1)
+        .max()
+        .unwrap_or(0);
+
+    let mut xml = format!("<sync rows=\"{}\">\n\t<tracks>\n", rows);
+    for track in tracks {
+        xml += &format!(
+            "\t\t<track name=\"{}\">\n",
+            escape_str_attribute(&track.name)
+        );
+        for key in &track.keys {
+            xml += &format!(
+                "\t\t\t<key interpolation=\"{}\" value=\"{}\" row=\"{}\"/>\n",
+                key.interpolation as u8, key.value, key.row
+            );
+        }
+        xml += "\t\t</track>\n";
+    }
+    xml += "\t</tracks>\n</sync>\n";
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_unknown_interpolation() {
+        let xml = br#"<sync rows="8"><tracks><track name="cam:x">
+            <key interpolation="3" value="1" row="0"/>
+            <key interpolation="4" value="2" row="5"/>
+        </track></tracks></sync>"#;
+        let error = format!("{:#}", parse(xml).unwrap_err());
+        assert!(error.contains("cam:x row 5"), "{}", error);
+        assert!(error.contains("Unknown interpolation 4"), "{}", error);
+    }
+}
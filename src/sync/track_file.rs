@@ -0,0 +1,317 @@
+use super::rocket_xml;
+use anyhow::{anyhow, Context, Result};
+use rust_rocket::{
+    interpolation::Interpolation,
+    track::{Key, Track},
+};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+const CSV_HEADER: &str = "track,row,value,interpolation";
+
+/// Key of a track, which rust_rocket doesn't expose
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct KeyData {
+    pub row: u32,
+    pub value: f32,
+    pub interpolation: Interpolation,
+}
+
+/// Name and keys of a track, serialized the same way as [`Track`]
+#[derive(Serialize, Deserialize)]
+pub struct TrackData {
+    pub name: String,
+    pub keys: Vec<KeyData>,
+}
+
+impl TrackData {
+    pub(super) fn from_tracks(tracks: &[Track]) -> Result<Vec<Self>> {
+        // Same layout, so bincode can convert to types with public fields
+        let bytes = bincode::serialize(tracks)?;
+        Ok(bincode::deserialize(&bytes)?)
+    }
+
+    fn into_track(self) -> Track {
+        let mut track = Track::new(self.name);
+        for key in self.keys {
+            track.set_key(Key::new(key.row, key.value, key.interpolation));
+        }
+        track
+    }
+}
+
+fn interpolation_name(interpolation: Interpolation) -> &'static str {
+    match interpolation {
+        Interpolation::Step => "step",
+        Interpolation::Linear => "linear",
+        Interpolation::Smooth => "smooth",
+        Interpolation::Ramp => "ramp",
+    }
+}
+
+fn parse_interpolation(name: &str) -> Result<Interpolation> {
+    match name {
+        "step" => Ok(Interpolation::Step),
+        "linear" => Ok(Interpolation::Linear),
+        "smooth" => Ok(Interpolation::Smooth),
+        "ramp" => Ok(Interpolation::Ramp),
+        _ => Err(anyhow!("Unknown interpolation {}", name)),
+    }
+}
+
+/// One key per line, tracks without keys as a line with just the name
+fn write_csv(tracks: &[TrackData]) -> String {
+    let mut csv = format!("{}\n", CSV_HEADER);
+    for track in tracks {
+        let name = if track.name.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", track.name.replace('"', "\"\""))
+        } else {
+            track.name.clone()
+        };
+        if track.keys.is_empty() {
+            csv += &format!("{},,,\n", name);
+        }
+        for key in &track.keys {
+            csv += &format!(
+                "{},{},{},{}\n",
+                name,
+                key.row,
+                key.value,
+                interpolation_name(key.interpolation)
+            );
+        }
+    }
+    csv
+}
+
+/// Split a CSV line to its track name and the rest of the fields
+fn split_name(line: &str) -> Result<(String, &str)> {
+    match line.strip_prefix('"') {
+        Some(quoted) => {
+            let mut name = String::new();
+            let mut chars = quoted.char_indices();
+            while let Some((i, c)) = chars.next() {
+                match (c, quoted[i + 1..].starts_with('"')) {
+                    ('"', true) => {
+                        name.push('"');
+                        chars.next();
+                    }
+                    ('"', false) => {
+                        let rest = quoted[i + 1..]
+                            .strip_prefix(',')
+                            .context("Expected , after track name")?;
+                        return Ok((name, rest));
+                    }
+                    _ => name.push(c),
+                }
+            }
+            Err(anyhow!("Unterminated quote"))
+        }
+        None => {
+            let (name, rest) = line
+                .split_once(',')
+                .context("Expected , after track name")?;
+            Ok((name.to_owned(), rest))
+        }
+    }
+}
+
+/// Split text to the first CSV record and the text after it, line breaks in quotes are
+/// part of the record
+fn split_record(text: &str) -> (&str, &str) {
+    let mut quoted = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '\n' if !quoted => {
+                let record = &text[..i];
+                return (record.strip_suffix('\r').unwrap_or(record), &text[i + 1..]);
+            }
+            _ => {}
+        }
+    }
+    (text, "")
+}
+
+fn parse_csv(text: &str) -> Result<Vec<TrackData>> {
+    let mut tracks: Vec<TrackData> = Vec::new();
+    let (mut text, mut line) = (text, 1);
+    while !text.is_empty() {
+        let (record, rest) = split_record(text);
+        let record_line = line;
+        text = rest;
+        line += record.matches('\n').count() + 1;
+        if record.is_empty() || (record_line == 1 && record == CSV_HEADER) {
+            continue;
+        }
+        (|| -> Result<()> {
+            let (name, rest) = split_name(record)?;
+            if tracks.last().map(|track| &track.name) != Some(&name) {
+                tracks.push(TrackData {
+                    name,
+                    keys: Vec::new(),
+                });
+            }
+            let keys = &mut tracks.last_mut().unwrap().keys;
+            match rest.split(',').collect::<Vec<_>>().as_slice() {
+                ["", "", ""] => {}
+                [row, value, interpolation] => keys.push(KeyData {
+                    row: row
+                        .parse()
+                        .with_context(|| format!("Invalid row {}", row))?,
+                    value: value
+                        .parse()
+                        .with_context(|| format!("Invalid value {}", value))?,
+                    interpolation: parse_interpolation(interpolation)?,
+                }),
+                _ => return Err(anyhow!("Expected 4 fields")),
+            }
+            Ok(())
+        })()
+        .with_context(|| format!("Line {}", record_line))?;
+    }
+    Ok(tracks)
+}
+
+/// Read tracks from tracks.bin, Rocket XML or CSV, depending on the file extension
+pub fn read(path: &Path) -> Result<Vec<Track>> {
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    match extension {
+        "bin" => bincode::deserialize(&data).context("Failed to deserialize tracks"),
+        "rocket" | "xml" => rocket_xml::parse(&data),
+        "csv" => Ok(parse_csv(std::str::from_utf8(&data)?)?
+            .into_iter()
+            .map(TrackData::into_track)
+            .collect()),
+        _ => Err(anyhow!("Unknown track file type {}", path.display())),
+    }
+    .with_context(|| format!("Failed to read tracks from {}", path.display()))
+}
+
+/// Write tracks to tracks.bin, Rocket XML or CSV, depending on the file extension
+pub fn write(path: &Path, tracks: &[Track]) -> Result<()> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    let data = match extension {
+        "bin" => bincode::serialize(tracks)?,
+        "rocket" | "xml" => rocket_xml::write(&TrackData::from_tracks(tracks)?).into_bytes(),
+        "csv" => write_csv(&TrackData::from_tracks(tracks)?).into_bytes(),
+        _ => return Err(anyhow!("Unknown track file type {}", path.display())),
+    };
+    std::fs::write(path, data).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Convert a track file to another format, see [`read`] and [`write`] for supported formats
+pub fn convert(input: &Path, output: &Path) -> Result<()> {
+    write(output, &read(input)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Keys = &'static [(u32, f32, Interpolation)];
+
+    // Names which need escaping in XML or quoting in CSV, and keys of every interpolation
+    const TRACKS: &[(&str, Keys)] = &[
+        (
+            "camera:x",
+            &[
+                (0, 0., Interpolation::Step),
+                (4, -1.5, Interpolation::Linear),
+                (10, 0.1, Interpolation::Smooth),
+                (300, 12345.678, Interpolation::Ramp),
+            ],
+        ),
+        ("empty", &[]),
+        (
+            "a, \"quoted\" <name> & more",
+            &[(7, 1e-7, Interpolation::Linear)],
+        ),
+        ("two\nlines\r\n", &[(1, 2., Interpolation::Step)]),
+    ];
+
+    fn tracks() -> Vec<Track> {
+        TRACKS
+            .iter()
+            .map(|&(name, keys)| {
+                let mut track = Track::new(name);
+                for &(row, value, interpolation) in keys {
+                    track.set_key(Key::new(row, value, interpolation));
+                }
+                track
+            })
+            .collect()
+    }
+
+    /// Check that tracks have the names and keys of [`TRACKS`]
+    fn assert_tracks(tracks: &[Track]) {
+        let tracks = TrackData::from_tracks(tracks).unwrap();
+        assert_eq!(tracks.len(), TRACKS.len());
+        for (track, &(name, keys)) in tracks.iter().zip(TRACKS) {
+            assert_eq!(track.name, name);
+            let keys: Vec<_> = keys
+                .iter()
+                .map(|&(row, value, interpolation)| (row, value, interpolation as u8))
+                .collect();
+            let read: Vec<_> = track
+                .keys
+                .iter()
+                .map(|key| (key.row, key.value, key.interpolation as u8))
+                .collect();
+            assert_eq!(read, keys, "{:?}", name);
+        }
+    }
+
+    /// Convert tracks.bin to a file with `extension` and back
+    fn round_trip(extension: &str) {
+        let dir =
+            std::env::temp_dir().join(format!("track-file-{}-{}", extension, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (bin, converted, back) = (
+            dir.join("tracks.bin"),
+            dir.join(format!("tracks.{}", extension)),
+            dir.join("back.bin"),
+        );
+        write(&bin, &tracks()).unwrap();
+        convert(&bin, &converted).unwrap();
+        convert(&converted, &back).unwrap();
+
+        assert_tracks(&read(&converted).unwrap());
+        assert_tracks(&read(&back).unwrap());
+        assert_eq!(std::fs::read(&back).unwrap(), std::fs::read(&bin).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn from_tracks_reads_all_keys() {
+        assert_tracks(&tracks());
+    }
+
+    /// Fails when rust_rocket's private track layout no longer matches [`TrackData`]
+    #[test]
+    fn matches_rust_rocket_layout() {
+        let tracks = tracks();
+        let data = TrackData::from_tracks(&tracks).unwrap();
+        assert_eq!(
+            bincode::serialize(&data).unwrap(),
+            bincode::serialize(&tracks).unwrap()
+        );
+        for (track, data) in tracks.iter().zip(&data) {
+            assert_eq!(track.get_name(), data.name);
+            for key in &data.keys {
+                assert_eq!(track.get_value(key.row as f32), key.value, "{}", data.name);
+            }
+        }
+    }
+
+    #[test]
+    fn xml_round_trip() {
+        round_trip("xml");
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        round_trip("csv");
+    }
+}